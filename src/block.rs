@@ -0,0 +1,170 @@
+// ===== Generic Block Device =====
+
+use crate::{BLOCK_SIZE, err::SdError};
+
+/// Block storage as seen by filesystems and other upper layers.
+///
+/// Buffers are plain byte slices whose length is a multiple of `block_size()`,
+/// independent of whether the driver moves data by PIO or DMA.
+pub trait BlockDevice {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    /// Number of addressable blocks
+    fn num_blocks(&self) -> u64;
+
+    /// Read `buf.len() / block_size()` blocks starting at `block_id`
+    fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<(), SdError>;
+
+    /// Write `buf.len() / block_size()` blocks starting at `block_id`
+    fn write(&self, block_id: u64, buf: &[u8]) -> Result<(), SdError>;
+
+    /// Make previous writes durable
+    fn flush(&self) -> Result<(), SdError> {
+        Ok(())
+    }
+
+    /// Hint that `blocks` blocks starting at `block_id` are no longer in use.
+    /// Their content is undefined afterwards.
+    fn discard(&self, _block_id: u64, _blocks: u64) -> Result<(), SdError> {
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+impl<T: BlockDevice + ?Sized> BlockDevice for &T {
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        (**self).num_blocks()
+    }
+
+    fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<(), SdError> {
+        (**self).read(block_id, buf)
+    }
+
+    fn write(&self, block_id: u64, buf: &[u8]) -> Result<(), SdError> {
+        (**self).write(block_id, buf)
+    }
+
+    fn flush(&self) -> Result<(), SdError> {
+        (**self).flush()
+    }
+
+    fn discard(&self, block_id: u64, blocks: u64) -> Result<(), SdError> {
+        (**self).discard(block_id, blocks)
+    }
+
+    fn is_read_only(&self) -> bool {
+        (**self).is_read_only()
+    }
+}
+
+/// Number of whole blocks in a buffer of `len` bytes
+pub(crate) fn buf_blocks(len: usize, block_size: usize) -> Result<u64, SdError> {
+    if len % block_size != 0 {
+        return Err(SdError::InvalidArgument);
+    }
+
+    Ok((len / block_size) as u64)
+}
+
+/// A contiguous range of blocks on another device, addressed from zero.
+///
+/// Accesses outside the range fail with `SdError::OutOfRange`.
+pub struct Partition<D> {
+    dev: D,
+    start: u64,
+    num_blocks: u64,
+    read_only: bool,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    pub fn new(dev: D, start: u64, num_blocks: u64) -> Result<Self, SdError> {
+        let end = start.checked_add(num_blocks).ok_or(SdError::OutOfRange)?;
+        if end > dev.num_blocks() {
+            return Err(SdError::OutOfRange);
+        }
+
+        Ok(Self {
+            dev,
+            start,
+            num_blocks,
+            read_only: false,
+        })
+    }
+
+    /// Reject writes and discards through this partition
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// First block of the partition on the underlying device
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.dev
+    }
+
+    /// Translate a partition relative range into a device block address
+    fn map(&self, block_id: u64, blocks: u64) -> Result<u64, SdError> {
+        let end = block_id.checked_add(blocks).ok_or(SdError::OutOfRange)?;
+        if end > self.num_blocks {
+            return Err(SdError::OutOfRange);
+        }
+
+        Ok(self.start + block_id)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    fn block_size(&self) -> usize {
+        self.dev.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<(), SdError> {
+        let blocks = buf_blocks(buf.len(), self.block_size())?;
+        let lba = self.map(block_id, blocks)?;
+        self.dev.read(lba, buf)
+    }
+
+    fn write(&self, block_id: u64, buf: &[u8]) -> Result<(), SdError> {
+        if self.read_only {
+            return Err(SdError::WriteProtected);
+        }
+
+        let blocks = buf_blocks(buf.len(), self.block_size())?;
+        let lba = self.map(block_id, blocks)?;
+        self.dev.write(lba, buf)
+    }
+
+    fn flush(&self) -> Result<(), SdError> {
+        self.dev.flush()
+    }
+
+    fn discard(&self, block_id: u64, blocks: u64) -> Result<(), SdError> {
+        if self.read_only {
+            return Err(SdError::WriteProtected);
+        }
+
+        let lba = self.map(block_id, blocks)?;
+        self.dev.discard(lba, blocks)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only || self.dev.is_read_only()
+    }
+}
@@ -0,0 +1,361 @@
+// ===== Block Cache =====
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use log::{trace, warn};
+use spin::Mutex;
+
+use crate::{
+    block::{BlockDevice, buf_blocks},
+    err::SdError,
+};
+
+/// Number of cached blocks used by `BlockCache::new`
+pub const DEFAULT_CACHE_BLOCKS: usize = 64;
+
+/// Hit/miss counters of a `BlockCache`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Blocks fetched speculatively by read-ahead
+    pub read_ahead: u64,
+    /// Dirty blocks written back to the device
+    pub write_backs: u64,
+}
+
+struct CacheEntry {
+    data: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+struct CacheState {
+    entries: BTreeMap<u64, CacheEntry>,
+    tick: u64,
+    /// Block following the previous read, for sequential access detection
+    next_sequential: u64,
+    sequential_reads: u32,
+    stats: CacheStats,
+}
+
+impl CacheState {
+    fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            tick: 0,
+            next_sequential: u64::MAX,
+            sequential_reads: 0,
+            stats: CacheStats::default(),
+        }
+    }
+}
+
+/// Write-back LRU cache in front of a block device.
+///
+/// Writes stay in memory until `flush()`, eviction or drop. Requests at
+/// least as large as the cache bypass it, keeping cached copies coherent.
+pub struct BlockCache<D: BlockDevice> {
+    dev: D,
+    capacity: usize,
+    read_ahead: usize,
+    state: Mutex<CacheState>,
+}
+
+impl<D: BlockDevice> BlockCache<D> {
+    pub fn new(dev: D) -> Self {
+        Self::with_capacity(dev, DEFAULT_CACHE_BLOCKS)
+    }
+
+    pub fn with_capacity(dev: D, capacity: usize) -> Self {
+        Self {
+            dev,
+            capacity: capacity.max(1),
+            read_ahead: 0,
+            state: Mutex::new(CacheState::new()),
+        }
+    }
+
+    /// Fetch up to `blocks` extra blocks when sequential reads are detected
+    pub fn with_read_ahead(mut self, blocks: usize) -> Self {
+        self.read_ahead = blocks.min(self.capacity / 2);
+        self
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.dev
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock().stats
+    }
+
+    /// Drop all cached blocks without writing back dirty data
+    pub fn invalidate(&self) {
+        self.state.lock().entries.clear();
+    }
+
+    fn touch(state: &mut CacheState, lba: u64) -> Option<&mut CacheEntry> {
+        state.tick += 1;
+        let tick = state.tick;
+        let entry = state.entries.get_mut(&lba)?;
+        entry.last_used = tick;
+        Some(entry)
+    }
+
+    /// Make room for one more block, writing back the evicted block if dirty
+    fn evict(&self, state: &mut CacheState) -> Result<(), SdError> {
+        while state.entries.len() >= self.capacity {
+            let (&lba, _) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .unwrap();
+            let entry = state.entries.remove(&lba).unwrap();
+            if entry.dirty {
+                trace!("Evicting dirty block {:#x}", lba);
+                if let Err(e) = self.dev.write(lba, &entry.data) {
+                    state.entries.insert(lba, entry);
+                    return Err(e);
+                }
+                state.stats.write_backs += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert(
+        &self,
+        state: &mut CacheState,
+        lba: u64,
+        data: &[u8],
+        dirty: bool,
+    ) -> Result<(), SdError> {
+        if let Some(entry) = Self::touch(state, lba) {
+            entry.data.copy_from_slice(data);
+            entry.dirty |= dirty;
+            return Ok(());
+        }
+
+        self.evict(state)?;
+        state.tick += 1;
+        let last_used = state.tick;
+        state.entries.insert(
+            lba,
+            CacheEntry {
+                data: data.to_vec(),
+                dirty,
+                last_used,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Read `count` blocks from the device into the cache and `buf`.
+    ///
+    /// Blocks past `buf` are read-ahead and only cached. Blocks already in
+    /// the cache keep their (possibly dirty) cached content.
+    fn fill(
+        &self,
+        state: &mut CacheState,
+        lba: u64,
+        count: usize,
+        buf: &mut [u8],
+    ) -> Result<(), SdError> {
+        let bs = self.block_size();
+        let mut data = vec![0u8; count * bs];
+        self.dev.read(lba, &mut data)?;
+
+        for (i, block) in data.chunks_exact(bs).enumerate() {
+            let block_lba = lba + i as u64;
+            let cached = Self::touch(state, block_lba).map(|entry| entry.data.clone());
+            let block = match &cached {
+                Some(cached) => cached.as_slice(),
+                None => {
+                    self.insert(state, block_lba, block, false)?;
+                    block
+                }
+            };
+
+            if let Some(dst) = buf.get_mut(i * bs..(i + 1) * bs) {
+                dst.copy_from_slice(block);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write every dirty block back, merging contiguous blocks into one request
+    fn write_back(&self, state: &mut CacheState) -> Result<(), SdError> {
+        let dirty: Vec<u64> = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(&lba, _)| lba)
+            .collect();
+
+        let mut i = 0;
+        while i < dirty.len() {
+            let start = dirty[i];
+            let mut end = i + 1;
+            while end < dirty.len() && dirty[end] == start + (end - i) as u64 {
+                end += 1;
+            }
+
+            let mut data = Vec::with_capacity((end - i) * self.block_size());
+            for lba in &dirty[i..end] {
+                data.extend_from_slice(&state.entries[lba].data);
+            }
+            self.dev.write(start, &data)?;
+
+            for lba in &dirty[i..end] {
+                state.entries.get_mut(lba).unwrap().dirty = false;
+            }
+            state.stats.write_backs += (end - i) as u64;
+            i = end;
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for BlockCache<D> {
+    fn block_size(&self) -> usize {
+        self.dev.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.dev.num_blocks()
+    }
+
+    fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<(), SdError> {
+        let bs = self.block_size();
+        let blocks = buf_blocks(buf.len(), bs)? as usize;
+        let mut state = self.state.lock();
+
+        // Sequential access detection for read-ahead
+        if block_id == state.next_sequential {
+            state.sequential_reads = state.sequential_reads.saturating_add(1);
+        } else {
+            state.sequential_reads = 0;
+        }
+        state.next_sequential = block_id + blocks as u64;
+
+        if blocks >= self.capacity {
+            // Too large to cache, read around it and overlay newer cached data
+            self.dev.read(block_id, buf)?;
+            state.stats.misses += blocks as u64;
+            for (lba, entry) in state.entries.range(block_id..block_id + blocks as u64) {
+                let offset = (lba - block_id) as usize * bs;
+                buf[offset..offset + bs].copy_from_slice(&entry.data);
+            }
+            return Ok(());
+        }
+
+        let mut i = 0;
+        while i < blocks {
+            let lba = block_id + i as u64;
+            if let Some(entry) = Self::touch(&mut state, lba) {
+                buf[i * bs..(i + 1) * bs].copy_from_slice(&entry.data);
+                state.stats.hits += 1;
+                i += 1;
+                continue;
+            }
+
+            // Gather the run of missing blocks
+            let mut end = i + 1;
+            while end < blocks && !state.entries.contains_key(&(block_id + end as u64)) {
+                end += 1;
+            }
+            state.stats.misses += (end - i) as u64;
+
+            let mut count = end - i;
+            if end == blocks && state.sequential_reads > 0 && self.read_ahead > 0 {
+                let limit = self.num_blocks().saturating_sub(lba + count as u64) as usize;
+                let extra = self.read_ahead.min(limit);
+                state.stats.read_ahead += extra as u64;
+                count += extra;
+            }
+
+            self.fill(&mut state, lba, count, &mut buf[i * bs..end * bs])?;
+            i = end;
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, block_id: u64, buf: &[u8]) -> Result<(), SdError> {
+        if self.is_read_only() {
+            return Err(SdError::WriteProtected);
+        }
+
+        let bs = self.block_size();
+        let blocks = buf_blocks(buf.len(), bs)? as usize;
+        let end = block_id
+            .checked_add(blocks as u64)
+            .ok_or(SdError::OutOfRange)?;
+        if end > self.num_blocks() {
+            return Err(SdError::OutOfRange);
+        }
+
+        let mut state = self.state.lock();
+
+        if blocks >= self.capacity {
+            // Write through and drop the stale cached copies
+            self.dev.write(block_id, buf)?;
+            let stale: Vec<u64> = state
+                .entries
+                .range(block_id..end)
+                .map(|(&lba, _)| lba)
+                .collect();
+            for lba in stale {
+                state.entries.remove(&lba);
+            }
+            return Ok(());
+        }
+
+        for (i, data) in buf.chunks_exact(bs).enumerate() {
+            self.insert(&mut state, block_id + i as u64, data, true)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), SdError> {
+        let mut state = self.state.lock();
+        self.write_back(&mut state)?;
+        self.dev.flush()
+    }
+
+    fn discard(&self, block_id: u64, blocks: u64) -> Result<(), SdError> {
+        let mut state = self.state.lock();
+        let end = block_id.saturating_add(blocks);
+        let discarded: Vec<u64> = state
+            .entries
+            .range(block_id..end)
+            .map(|(&lba, _)| lba)
+            .collect();
+        for lba in discarded {
+            state.entries.remove(&lba);
+        }
+
+        self.dev.discard(block_id, blocks)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.dev.is_read_only()
+    }
+}
+
+impl<D: BlockDevice> Drop for BlockCache<D> {
+    fn drop(&mut self) {
+        let mut state = core::mem::replace(self.state.get_mut(), CacheState::new());
+        if state.entries.values().any(|entry| entry.dirty) {
+            if let Err(e) = self.write_back(&mut state) {
+                warn!("Block cache dropped with unwritten data: {:?}", e);
+            }
+        }
+    }
+}
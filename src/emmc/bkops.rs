@@ -0,0 +1,223 @@
+use core::sync::atomic::Ordering;
+
+use log::{debug, info, warn};
+
+use super::{
+    EMmcHost,
+    aux::{MMC_VERSION_4_41, MMC_VERSION_5_0},
+    cmd::EMmcCommand,
+    constant::*,
+};
+use crate::err::SdError;
+
+/// Pending background operations level reported through BKOPS_STATUS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BkopsStatus {
+    None,
+    NonCritical,
+    PerformanceImpacted,
+    Critical,
+}
+
+impl From<u8> for BkopsStatus {
+    fn from(value: u8) -> Self {
+        match value & 0x3 {
+            EXT_CSD_BKOPS_LEVEL_NONE => BkopsStatus::None,
+            EXT_CSD_BKOPS_LEVEL_NON_CRITICAL => BkopsStatus::NonCritical,
+            EXT_CSD_BKOPS_LEVEL_IMPACTED => BkopsStatus::PerformanceImpacted,
+            _ => BkopsStatus::Critical,
+        }
+    }
+}
+
+impl BkopsStatus {
+    /// Levels at which the host should schedule manual BKOPS as soon as it is idle
+    pub fn is_urgent(&self) -> bool {
+        *self >= BkopsStatus::PerformanceImpacted
+    }
+}
+
+impl EMmcHost {
+    fn mmc_check_bkops_support(&self) -> Result<(), SdError> {
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+
+        if !card.initialized.load(Ordering::SeqCst)
+            || card.version < MMC_VERSION_4_41
+            || !card.bkops_support
+        {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        Ok(())
+    }
+
+    /// Read the pending background operations level from EXT_CSD
+    pub fn bkops_status(&mut self) -> Result<BkopsStatus, SdError> {
+        self.mmc_reinit_if_needed()?;
+        self.mmc_check_bkops_support()?;
+        self.mmc_bkops_preempt()?;
+
+        let ext_csd = self.mmc_read_ext_csd()?;
+
+        Ok(BkopsStatus::from(ext_csd[EXT_CSD_BKOPS_STATUS as usize]))
+    }
+
+    /// Enable or disable automatic BKOPS (AUTO_EN, eMMC 5.0+).
+    ///
+    /// With AUTO_EN set the device runs background operations on its own
+    /// whenever it is idle, without host involvement. Older devices return
+    /// `SdError::UnsupportedCard`.
+    pub fn set_auto_bkops(&mut self, enable: bool) -> Result<(), SdError> {
+        self.mmc_reinit_if_needed()?;
+        self.mmc_check_bkops_support()?;
+
+        // AUTO_EN was introduced with eMMC 5.0 (EXT_CSD_REV 7)
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+        if card.version < MMC_VERSION_5_0 {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        self.mmc_bkops_preempt()?;
+
+        let bkops_en = self.bkops_en().unwrap_or(0);
+        let value = if enable {
+            bkops_en | EXT_CSD_AUTO_BKOPS_EN
+        } else {
+            bkops_en & !EXT_CSD_AUTO_BKOPS_EN
+        };
+
+        if value != bkops_en {
+            self.mmc_switch(EXT_CSD_CMD_SET_NORMAL, EXT_CSD_BKOPS_EN, value, true)?;
+            self.set_bkops_en(value).unwrap();
+        }
+
+        Ok(())
+    }
+
+    /// Enable host-initiated (manual) BKOPS and the urgent BKOPS exception event.
+    ///
+    /// MANUAL_EN is one-time programmable on many devices and can never be
+    /// cleared again. Unless it is already set, `permanent` must be true to
+    /// confirm that, otherwise `SdError::InvalidArgument` is returned.
+    pub fn enable_manual_bkops(&mut self, permanent: bool) -> Result<(), SdError> {
+        self.mmc_reinit_if_needed()?;
+        self.mmc_check_bkops_support()?;
+        self.mmc_bkops_preempt()?;
+
+        let bkops_en = self.bkops_en().unwrap_or(0);
+        if bkops_en & EXT_CSD_MANUAL_BKOPS_EN == 0 {
+            if !permanent {
+                warn!("Enabling manual BKOPS is permanent, not confirmed");
+                return Err(SdError::InvalidArgument);
+            }
+
+            let value = bkops_en | EXT_CSD_MANUAL_BKOPS_EN;
+            self.mmc_switch(EXT_CSD_CMD_SET_NORMAL, EXT_CSD_BKOPS_EN, value, true)?;
+            self.set_bkops_en(value).unwrap();
+        }
+
+        // Ask the device to flag urgent BKOPS through EXCEPTION_EVENT in R1,
+        // keeping the events already enabled
+        if self.ext_csd_rev().unwrap_or(0) >= 6 {
+            let ext_csd = self.mmc_read_ext_csd()?;
+            let events = ext_csd[EXT_CSD_EXCEPTION_EVENTS_CTRL as usize];
+            if events & EXT_CSD_URGENT_BKOPS == 0 {
+                self.mmc_switch(
+                    EXT_CSD_CMD_SET_NORMAL,
+                    EXT_CSD_EXCEPTION_EVENTS_CTRL,
+                    events | EXT_CSD_URGENT_BKOPS,
+                    true,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start manual background operations.
+    ///
+    /// The card stays busy until the operations complete; the next read or
+    /// write interrupts them with HPI (or waits for them without HPI).
+    pub fn start_bkops(&mut self) -> Result<(), SdError> {
+        self.mmc_reinit_if_needed()?;
+        self.mmc_check_bkops_support()?;
+        self.mmc_bkops_preempt()?;
+
+        if self.bkops_en().unwrap_or(0) & EXT_CSD_MANUAL_BKOPS_EN == 0 {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        // Issue CMD6 without waiting for busy so BKOPS can run in the background
+        let cmd = EMmcCommand::new(
+            MMC_SWITCH,
+            (MMC_SWITCH_MODE_WRITE_BYTE << 24) | (EXT_CSD_BKOPS_START << 16) | (1 << 8),
+            MMC_RSP_R1B,
+        );
+        self.send_command(&cmd, None)?;
+
+        let card = self.card.as_ref().unwrap();
+        card.bkops_running.store(true, Ordering::SeqCst);
+        info!("Manual BKOPS started");
+
+        Ok(())
+    }
+
+    /// Idle-time BKOPS scheduling hook.
+    ///
+    /// Checks the EXCEPTION_EVENT bit in R1 and starts manual BKOPS when the
+    /// device reports an urgent level. Returns whether BKOPS is running.
+    pub fn bkops_idle(&mut self) -> Result<bool, SdError> {
+        self.mmc_reinit_if_needed()?;
+        self.mmc_check_bkops_support()?;
+
+        if self.bkops_en().unwrap_or(0) & EXT_CSD_MANUAL_BKOPS_EN == 0 {
+            return Ok(false);
+        }
+
+        let card = self.card.as_ref().unwrap();
+        if card.bkops_running.load(Ordering::SeqCst) {
+            if self.mmc_card_busy() {
+                return Ok(true);
+            }
+            card.bkops_running.store(false, Ordering::SeqCst);
+            debug!("Manual BKOPS completed");
+        }
+
+        let status = self.get_status()?;
+        if !status.exception_event() {
+            return Ok(false);
+        }
+
+        let ext_csd = self.mmc_read_ext_csd()?;
+        let level = BkopsStatus::from(ext_csd[EXT_CSD_BKOPS_STATUS as usize]);
+        let urgent_event = ext_csd[EXT_CSD_EXCEPTION_EVENTS_STATUS as usize] & EXT_CSD_URGENT_BKOPS;
+        debug!("BKOPS level {:?}, urgent event {}", level, urgent_event);
+
+        if !level.is_urgent() {
+            return Ok(false);
+        }
+
+        self.start_bkops()?;
+
+        Ok(true)
+    }
+
+    /// Stop running manual BKOPS before host I/O is issued to the card
+    pub(crate) fn mmc_bkops_preempt(&self) -> Result<(), SdError> {
+        let card = match self.card.as_ref() {
+            Some(card) => card,
+            None => return Ok(()),
+        };
+
+        if !card.bkops_running.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        if card.hpi_en && self.mmc_card_busy() {
+            debug!("Interrupting BKOPS with HPI");
+            self.mmc_send_hpi()
+        } else {
+            self.mmc_poll_for_busy(false)
+        }
+    }
+}
@@ -1,31 +1,57 @@
 // ===== Block Device Interface =====
 
 use aux::MMC_VERSION_UNKNOWN;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::time::Duration;
 
-#[cfg(feature = "dma")]
-use {
-    dma_api::DVec,
-    crate::delay_us,
-    log::{debug, info},
-};
+use dma_api::{DVec, Direction};
+use log::{debug, info, trace};
 
-use log::trace;
+use crate::{
+    BLOCK_SIZE,
+    block::{BlockDevice, buf_blocks},
+    err::SdError,
+};
 
-use crate::err::SdError;
+use super::{
+    CardState, CardType, EMmcHost, TransferMode, aux,
+    cmd::{EMMC_DEFAULT_BOUNDARY_ARG, EMmcCommand},
+    constant::*,
+};
 
-use super::{CardType, EMmcHost, aux, cmd::EMmcCommand, constant::*};
+/// Size of the SDMA buffer boundary programmed into the BLOCK_SIZE register
+const EMMC_SDMA_BOUNDARY_SIZE: usize = 4096 << EMMC_DEFAULT_BOUNDARY_ARG;
 
-#[cfg(feature = "pio")]
+/// Data of a command, `DVec` buffers are moved by SDMA, slices by PIO
 pub enum DataBuffer<'a> {
     Read(&'a mut [u8]),
     Write(&'a [u8]),
+    DmaRead(&'a mut DVec<u8>),
+    DmaWrite(&'a DVec<u8>),
 }
 
-#[cfg(feature = "dma")]
-pub enum DataBuffer<'a> {
-    Read(&'a mut DVec<u8>),
-    Write(&'a DVec<u8>),
+impl DataBuffer<'_> {
+    pub fn is_dma(&self) -> bool {
+        matches!(self, DataBuffer::DmaRead(_) | DataBuffer::DmaWrite(_))
+    }
+}
+
+/// Number of blocks the next command of a transfer may move.
+///
+/// Limited by the 16-bit block count register and, for SDMA, by the next
+/// buffer boundary after the bus address `dma_addr` where the controller
+/// would pause. 0 when the next block of an unaligned buffer straddles the
+/// boundary, SDMA cannot move it without a restart so it goes by PIO.
+fn mmc_chunk_blocks(remaining: u32, dma_addr: Option<usize>) -> u16 {
+    let mut count = remaining.min(MMC_MAX_BLOCK_COUNT);
+
+    if let Some(addr) = dma_addr {
+        let boundary = EMMC_SDMA_BOUNDARY_SIZE;
+        let to_boundary = ((boundary - addr % boundary) / 512) as u32;
+        count = count.min(to_boundary);
+    }
+
+    count as u16
 }
 
 // EMmc Card structure
@@ -40,6 +66,7 @@ pub struct EMmcCard {
     pub block_size: u32,
     pub capacity_blocks: u64,
     pub initialized: AtomicBool,
+    pub card_state: AtomicU8,
 
     pub high_capacity: bool,
     pub version: u32,
@@ -66,6 +93,24 @@ pub struct EMmcCard {
     pub enh_user_start: u64,
     pub raw_driver_strength: u8,
 
+    // 后台操作 (BKOPS) 与高优先级中断 (HPI)
+    pub bkops_support: bool,
+    pub bkops_en: u8,
+    pub bkops_running: AtomicBool,
+    pub hpi_support: bool,
+    pub hpi_cmd: u8,
+    pub hpi_en: bool,
+    pub out_of_int_time: u32,
+    pub can_trim: bool,
+    pub erase_timeout: u32,
+    pub trim_timeout: u32,
+    /// Default CMD6 busy time in ms, 0 if not reported
+    pub generic_cmd6_time: u32,
+    /// Partition switch busy time in ms, 0 if not reported
+    pub part_switch_time: u32,
+    /// EXT_CSD RST_n_FUNCTION
+    pub rst_n_function: u8,
+
     // 扩展CSD相关字段
     pub ext_csd_rev: u8,
     pub ext_csd_sectors: u64,
@@ -84,6 +129,7 @@ impl EMmcCard {
             block_size: 0,
             capacity_blocks: 0,
             initialized: AtomicBool::new(false),
+            card_state: AtomicU8::new(CardState::Idle.into()),
 
             version: MMC_VERSION_UNKNOWN,
             dsr: 0xffffffff,
@@ -111,6 +157,20 @@ impl EMmcCard {
             enh_user_start: 0,
             raw_driver_strength: 0,
 
+            bkops_support: false,
+            bkops_en: 0,
+            bkops_running: AtomicBool::new(false),
+            hpi_support: false,
+            hpi_cmd: MMC_STOP_TRANSMISSION,
+            hpi_en: false,
+            out_of_int_time: 0,
+            can_trim: false,
+            erase_timeout: 0,
+            trim_timeout: 0,
+            generic_cmd6_time: 0,
+            part_switch_time: 0,
+            rst_n_function: 0,
+
             ext_csd_rev: 0,
             ext_csd_sectors: 0,
             hs_max_dtr: 0,
@@ -178,128 +238,132 @@ impl EMmcHost {
         self.card = Some(card);
     }
 
-    /// Read one or more data blocks from the card
-    #[cfg(feature = "dma")]
-    pub fn read_blocks(
+    /// Read one or more data blocks from the card by DMA, whatever the
+    /// host transfer mode
+    ///
+    /// Requests of any length are split into chunks that fit the block count
+    /// register and do not cross an SDMA buffer boundary. A block of an
+    /// unaligned buffer that straddles a boundary is moved by PIO.
+    pub fn read_blocks_dma(
         &self,
-        block_id: u32,
-        blocks: u16,
+        block_id: u64,
+        blocks: u32,
         buffer: &mut DVec<u8>,
     ) -> Result<(), SdError> {
         // Check if buffer size matches the expected size based on number of blocks
-        let expected_size = blocks as usize * 512;
-        if buffer.len() != expected_size {
-            return Err(SdError::IoError);
-        }
+        mmc_check_buffer(blocks, buffer.len())?;
 
-        // Check if card is initialized and extract card information
-        let card = match &self.card {
-            Some(card) => card,
-            None => return Err(SdError::NoCard),
-        };
+        // Check if card is initialized and the request is within the card
+        self.mmc_check_range(block_id, blocks as u64)?;
 
-        // Adjust block address based on card type
-        // High capacity cards use block addressing, standard capacity cards use byte addressing
-        let card_addr = if card.state & MMC_STATE_HIGHCAPACITY != 0 {
-            block_id // High capacity card: use block address directly
-        } else {
-            block_id * 512 // Standard capacity card: convert to byte address
-        };
+        // Host I/O takes priority over manual background operations
+        self.mmc_bkops_preempt()?;
 
         trace!(
-            "Reading {} blocks starting at address: {:#x}",
-            blocks, card_addr
+            "Reading {} blocks starting at block: {:#x}",
+            blocks, block_id
         );
 
-        // Select appropriate command based on number of blocks
-        if blocks == 1 {
-            // Single block read operation
-            let cmd = EMmcCommand::new(MMC_READ_SINGLE_BLOCK, card_addr, MMC_RSP_R1)
-                .with_data(512, 1, true); // Configure for reading 512 bytes with DMA
-            self.send_command(&cmd, Some(DataBuffer::Read(buffer)))?;
-        } else {
-            // Multiple block read operation
-            let cmd = EMmcCommand::new(MMC_READ_MULTIPLE_BLOCK, card_addr, MMC_RSP_R1)
-                .with_data(512, blocks, true); // Configure for reading multiple blocks with DMA
-
-            self.send_command(&cmd, Some(DataBuffer::Read(buffer)))?;
-
-            // Must send stop transmission command after multiple block read
-            let stop_cmd = EMmcCommand::new(MMC_STOP_TRANSMISSION, 0, MMC_RSP_R1B);
-            self.send_command(&stop_cmd, None)?;
+        let mut done = 0;
+        while done < blocks {
+            let offset = done as usize * 512;
+            let card_addr = self.mmc_card_addr(block_id + done as u64)?;
+
+            let dma_addr = buffer.bus_addr() as usize + offset;
+            let count = match mmc_chunk_blocks(blocks - done, Some(dma_addr)) {
+                0 => {
+                    // The block straddles an SDMA boundary, move it by PIO
+                    let mut block = [0u8; 512];
+                    self.mmc_retry(|| {
+                        self.mmc_read_chunk(card_addr, 1, DataBuffer::Read(&mut block), 0)
+                    })?;
+                    for (i, &byte) in block.iter().enumerate() {
+                        buffer.set(offset + i, byte);
+                    }
+                    1
+                }
+                count => {
+                    self.mmc_retry(|| {
+                        self.mmc_read_chunk(
+                            card_addr,
+                            count,
+                            DataBuffer::DmaRead(&mut *buffer),
+                            offset,
+                        )
+                    })?;
+                    count
+                }
+            };
+            done += count as u32;
         }
 
         Ok(())
     }
 
-    /// Write multiple blocks to the card
-    #[cfg(feature = "dma")]
-    pub fn write_blocks(
+    /// Write multiple blocks to the card by DMA, whatever the host transfer
+    /// mode
+    ///
+    /// Requests of any length are split into chunks that fit the block count
+    /// register and do not cross an SDMA buffer boundary. A block of an
+    /// unaligned buffer that straddles a boundary is moved by PIO.
+    pub fn write_blocks_dma(
         &self,
-        block_id: u32,
-        blocks: u16,
+        block_id: u64,
+        blocks: u32,
         buffer: &DVec<u8>,
     ) -> Result<(), SdError> {
         // Verify that buffer size matches the requested number of blocks
-        let expected_size = blocks as usize * 512;
-        if buffer.len() != expected_size {
-            return Err(SdError::IoError);
-        }
+        mmc_check_buffer(blocks, buffer.len())?;
 
-        // Extract card information and check if card exists
-        let card = match &self.card {
-            Some(card) => card,
-            None => return Err(SdError::NoCard),
-        };
+        // Check if card is initialized and the request is within the card
+        self.mmc_check_range(block_id, blocks as u64)?;
 
-        // Check if card is properly initialized
-        if !card.initialized.load(Ordering::SeqCst) {
-            return Err(SdError::UnsupportedCard);
-        }
+        // Host I/O takes priority over manual background operations
+        self.mmc_bkops_preempt()?;
 
         // Check if card is write protected
-        if self.is_write_protected() {
-            return Err(SdError::IoError);
-        }
-
-        // Determine the correct address based on card capacity type
-        let card_addr = if card.state & MMC_STATE_HIGHCAPACITY != 0 {
-            block_id // High capacity card: use block address directly
-        } else {
-            block_id * 512 // Standard capacity card: convert to byte address
-        };
+        self.mmc_check_writable()?;
 
         trace!(
-            "Writing {} blocks starting at address: {:#x}",
-            blocks, card_addr
+            "Writing {} blocks starting at block: {:#x}",
+            blocks, block_id
         );
 
-        // Select appropriate command based on number of blocks
-        if blocks == 1 {
-            // Single block write operation
-            let cmd =
-                EMmcCommand::new(MMC_WRITE_BLOCK, card_addr, MMC_RSP_R1).with_data(512, 1, false); // Configure for writing 512 bytes with DMA (false = write)
-            self.send_command(&cmd, Some(DataBuffer::Write(buffer)))?;
-        } else {
-            // Multiple block write operation
-            let cmd = EMmcCommand::new(MMC_WRITE_MULTIPLE_BLOCK, card_addr, MMC_RSP_R1)
-                .with_data(512, blocks, false); // Configure for writing multiple blocks
-
-            self.send_command(&cmd, Some(DataBuffer::Write(buffer)))?;
-
-            // Must send stop transmission command after multiple block write
-            let stop_cmd = EMmcCommand::new(MMC_STOP_TRANSMISSION, 0, MMC_RSP_R1B);
-            self.send_command(&stop_cmd, None)?;
+        let mut done = 0;
+        while done < blocks {
+            let offset = done as usize * 512;
+            let card_addr = self.mmc_card_addr(block_id + done as u64)?;
+
+            let dma_addr = buffer.bus_addr() as usize + offset;
+            let count = match mmc_chunk_blocks(blocks - done, Some(dma_addr)) {
+                0 => {
+                    // The block straddles an SDMA boundary, move it by PIO
+                    let mut block = [0u8; 512];
+                    for (i, byte) in block.iter_mut().enumerate() {
+                        *byte = buffer[offset + i];
+                    }
+                    self.mmc_retry(|| {
+                        self.mmc_write_chunk(card_addr, 1, DataBuffer::Write(&block), 0)
+                    })?;
+                    1
+                }
+                count => {
+                    self.mmc_retry(|| {
+                        self.mmc_write_chunk(card_addr, count, DataBuffer::DmaWrite(buffer), offset)
+                    })?;
+                    count
+                }
+            };
+            done += count as u32;
         }
 
         Ok(())
     }
 
     /// Transfer data using DMA mode
-    /// This function polls for transfer completion or errors
-    #[cfg(feature = "dma")]
-    pub fn transfer_data_by_dma(&self) -> Result<(), SdError> {
-        let mut timeout = 100;
+    /// This function polls for transfer completion or errors, giving up after `timeout`
+    pub fn transfer_data_by_dma(&self, timeout: Duration) -> Result<(), SdError> {
+        let deadline = self.deadline(timeout)?;
 
         loop {
             // Read the interrupt status register
@@ -314,20 +378,8 @@ impl EMmcHost {
                     stat, err_status
                 );
 
-                // Reset the data circuit to recover from error
-                self.reset_data()?;
-
-                // Determine specific error type based on error status bits
-                let err = if err_status & 0x10 != 0 {
-                    SdError::DataTimeout
-                } else if err_status & 0x20 != 0 {
-                    SdError::DataCrc
-                } else if err_status & 0x40 != 0 {
-                    SdError::DataEndBit
-                } else {
-                    SdError::DataError
-                };
-                return Err(err);
+                // send_command resets the data circuit once the status is recorded
+                return Err(mmc_data_error(err_status));
             }
 
             // Check if data transfer is complete
@@ -338,121 +390,197 @@ impl EMmcHost {
             }
 
             // Handle timeout to prevent infinite loop
-            if timeout > 0 {
-                timeout -= 1;
-                delay_us(1000); // Wait 1ms before checking again
-            } else {
+            if deadline.expired() {
                 info!("Data transfer timeout");
                 return Err(SdError::DataTimeout);
             }
+            self.mmc_relax()?;
         }
 
         Ok(())
     }
 
-    /// Read blocks from SD card using PIO (Programmed I/O) mode
+    /// Read blocks from SD card in the host transfer mode
     /// Parameters:
     /// - block_id: Starting block address to read from
-    /// - blocks: Number of blocks to read
+    /// - blocks: Number of blocks to read, split into several commands if needed
     /// - buffer: Buffer to store the read data
-    #[cfg(feature = "pio")]
     pub fn read_blocks(
         &self,
-        block_id: u32,
-        blocks: u16,
+        block_id: u64,
+        blocks: u32,
         buffer: &mut [u8],
     ) -> Result<(), SdError> {
+        mmc_check_buffer(blocks, buffer.len())?;
+
+        if self.transfer_mode == TransferMode::Dma {
+            // Bounce through a DMA capable buffer
+            let mut dma_buf: DVec<u8> = DVec::zeros(buffer.len(), 0x1000, Direction::FromDevice)
+                .ok_or(SdError::MemoryError)?;
+            self.read_blocks_dma(block_id, blocks, &mut dma_buf)?;
+            buffer.copy_from_slice(&dma_buf);
+            return Ok(());
+        }
+
         trace!(
             "pio read_blocks: block_id = {}, blocks = {}",
             block_id, blocks
         );
-        // Check if card is initialized
-        let card = match &self.card {
-            Some(card) => card,
-            None => return Err(SdError::NoCard),
-        };
 
-        // Adjust block address based on card type
-        // High capacity cards use block addressing, standard capacity cards use byte addressing
-        let card_addr = if card.state & MMC_STATE_HIGHCAPACITY != 0 {
-            block_id // High capacity card: use block address directly
-        } else {
-            block_id * 512 // Standard capacity card: convert to byte address
-        };
+        // Check if card is initialized and the request is within the card
+        self.mmc_check_range(block_id, blocks as u64)?;
 
-        trace!(
-            "Reading {} blocks starting at address: {:#x}",
-            blocks, card_addr
-        );
+        // Host I/O takes priority over manual background operations
+        self.mmc_bkops_preempt()?;
 
-        if blocks == 1 {
-            // Single block read operation
-            let cmd = EMmcCommand::new(MMC_READ_SINGLE_BLOCK, card_addr, MMC_RSP_R1)
-                .with_data(512, 1, true);
-            self.send_command(&cmd, Some(DataBuffer::Read(buffer)))?;
-        } else {
-            // Multiple block read operation
-            let cmd = EMmcCommand::new(MMC_READ_MULTIPLE_BLOCK, card_addr, MMC_RSP_R1)
-                .with_data(512, blocks, true);
+        let mut done = 0;
+        while done < blocks {
+            let count = mmc_chunk_blocks(blocks - done, None);
+            let card_addr = self.mmc_card_addr(block_id + done as u64)?;
+            let chunk = &mut buffer[done as usize * 512..(done as usize + count as usize) * 512];
 
-            self.send_command(&cmd, Some(DataBuffer::Read(buffer)))?;
-
-            // Must send stop transmission command after multiple block read
-            let stop_cmd = EMmcCommand::new(MMC_STOP_TRANSMISSION, 0, MMC_RSP_R1B);
-            self.send_command(&stop_cmd, None)?;
+            self.mmc_retry(|| {
+                self.mmc_read_chunk(card_addr, count, DataBuffer::Read(&mut *chunk), 0)
+            })?;
+            done += count as u32;
         }
 
         Ok(())
     }
 
-    /// Write blocks to SD card using PIO (Programmed I/O) mode
+    /// Write blocks to SD card in the host transfer mode
     /// Parameters:
     /// - block_id: Starting block address to write to
-    /// - blocks: Number of blocks to write
+    /// - blocks: Number of blocks to write, split into several commands if needed
     /// - buffer: Buffer containing data to write
-    #[cfg(feature = "pio")]
-    pub fn write_blocks(&self, block_id: u32, blocks: u16, buffer: &[u8]) -> Result<(), SdError> {
-        use log::trace;
+    pub fn write_blocks(&self, block_id: u64, blocks: u32, buffer: &[u8]) -> Result<(), SdError> {
+        mmc_check_buffer(blocks, buffer.len())?;
+
+        if self.transfer_mode == TransferMode::Dma {
+            // Bounce through a DMA capable buffer, one copy and cache flush
+            let mut dma_buf: DVec<u8> = DVec::zeros(buffer.len(), 0x1000, Direction::ToDevice)
+                .ok_or(SdError::MemoryError)?;
+            dma_buf.copy_from_slice(buffer);
+            return self.write_blocks_dma(block_id, blocks, &dma_buf);
+        }
 
         trace!(
             "pio write_blocks: block_id = {}, blocks = {}",
             block_id, blocks
         );
-        // Check if card is initialized
+
+        // Check if card is initialized and the request is within the card
+        self.mmc_check_range(block_id, blocks as u64)?;
+
+        // Host I/O takes priority over manual background operations
+        self.mmc_bkops_preempt()?;
+
+        // Check if card is write protected
+        self.mmc_check_writable()?;
+
+        let mut done = 0;
+        while done < blocks {
+            let count = mmc_chunk_blocks(blocks - done, None);
+            let card_addr = self.mmc_card_addr(block_id + done as u64)?;
+            let chunk = &buffer[done as usize * 512..(done as usize + count as usize) * 512];
+
+            self.mmc_retry(|| self.mmc_write_chunk(card_addr, count, DataBuffer::Write(chunk), 0))?;
+            done += count as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Check that `blocks` blocks starting at `block_id` lie within the card
+    pub(crate) fn mmc_check_range(&self, block_id: u64, blocks: u64) -> Result<(), SdError> {
         let card = match &self.card {
             Some(card) => card,
             None => return Err(SdError::NoCard),
         };
 
-        // Check if card is write protected
-        if self.is_write_protected() {
-            return Err(SdError::IoError);
+        if !card.initialized.load(Ordering::SeqCst) {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        // A card without a known size cannot be range checked
+        if card.capacity_blocks == 0 {
+            debug!("Card capacity unknown, refusing block access");
+            return Err(SdError::UnsupportedCard);
+        }
+
+        let end = block_id.checked_add(blocks).ok_or(SdError::OutOfRange)?;
+        if end > card.capacity_blocks {
+            debug!(
+                "Access to blocks {:#x}..{:#x} beyond capacity {:#x}",
+                block_id, end, card.capacity_blocks
+            );
+            return Err(SdError::OutOfRange);
         }
 
-        // Determine the correct address based on card capacity type
-        let card_addr = if card.state & MMC_STATE_HIGHCAPACITY != 0 {
-            block_id // High capacity card: use block address directly
+        Ok(())
+    }
+
+    /// Command argument addressing block `lba`.
+    /// High capacity cards use block addressing, standard capacity cards use byte addressing.
+    pub(crate) fn mmc_card_addr(&self, lba: u64) -> Result<u32, SdError> {
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+
+        let addr = if card.state & MMC_STATE_HIGHCAPACITY != 0 {
+            lba
         } else {
-            block_id * 512 // Standard capacity card: convert to byte address
+            lba * 512
         };
 
-        trace!(
-            "Writing {} blocks starting at address: {:#x}",
-            blocks, card_addr
-        );
+        u32::try_from(addr).map_err(|_| SdError::OutOfRange)
+    }
 
-        // Select appropriate command based on number of blocks
+    fn mmc_read_chunk(
+        &self,
+        card_addr: u32,
+        blocks: u16,
+        data: DataBuffer,
+        offset: usize,
+    ) -> Result<(), SdError> {
+        if blocks == 1 {
+            // Single block read operation
+            let cmd = EMmcCommand::new(MMC_READ_SINGLE_BLOCK, card_addr, MMC_RSP_R1)
+                .with_data(512, 1, true)
+                .with_buffer_offset(offset);
+            self.send_command(&cmd, Some(data))?;
+        } else {
+            // Multiple block read operation
+            let cmd = EMmcCommand::new(MMC_READ_MULTIPLE_BLOCK, card_addr, MMC_RSP_R1)
+                .with_data(512, blocks, true)
+                .with_buffer_offset(offset);
+            self.send_command(&cmd, Some(data))?;
+
+            // Must send stop transmission command after multiple block read
+            let stop_cmd = EMmcCommand::new(MMC_STOP_TRANSMISSION, 0, MMC_RSP_R1B);
+            self.send_command(&stop_cmd, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn mmc_write_chunk(
+        &self,
+        card_addr: u32,
+        blocks: u16,
+        data: DataBuffer,
+        offset: usize,
+    ) -> Result<(), SdError> {
         if blocks == 1 {
             // Single block write operation
-            let cmd =
-                EMmcCommand::new(MMC_WRITE_BLOCK, card_addr, MMC_RSP_R1).with_data(512, 1, false);
-            self.send_command(&cmd, Some(DataBuffer::Write(buffer)))?;
+            let cmd = EMmcCommand::new(MMC_WRITE_BLOCK, card_addr, MMC_RSP_R1)
+                .with_data(512, 1, false)
+                .with_buffer_offset(offset);
+            self.send_command(&cmd, Some(data))?;
         } else {
             // Multiple block write operation
             let cmd = EMmcCommand::new(MMC_WRITE_MULTIPLE_BLOCK, card_addr, MMC_RSP_R1)
-                .with_data(512, blocks, false);
-
-            self.send_command(&cmd, Some(DataBuffer::Write(buffer)))?;
+                .with_data(512, blocks, false)
+                .with_buffer_offset(offset);
+            self.send_command(&cmd, Some(data))?;
 
             // Must send stop transmission command after multiple block write
             let stop_cmd = EMmcCommand::new(MMC_STOP_TRANSMISSION, 0, MMC_RSP_R1B);
@@ -533,33 +661,38 @@ impl EMmcHost {
     /// Write data to SD card buffer register
     /// This is a lower-level function used by data transfer operations
     pub fn write_buffer(&self, buffer: &[u8]) -> Result<(), SdError> {
-        // Wait until space is available in the controller buffer
-        self.wait_for_interrupt(EMMC_INT_SPACE_AVAIL, 100000)?;
+        let blocks = buffer.len().div_ceil(BLOCK_SIZE) as u32;
 
-        let len = buffer.len();
-        // Write data in 4-byte chunks
-        for i in (0..len).step_by(4) {
-            // Pack bytes into a 32-bit word, handling potential buffer underrun
-            let mut val: u32 = (buffer[i] as u32) << 0;
+        // The controller buffers one block at a time, wait for space
+        // before every block of a multi-block transfer
+        for block in buffer.chunks(BLOCK_SIZE) {
+            self.wait_for_interrupt(EMMC_INT_SPACE_AVAIL, self.timeouts.data_min)?;
 
-            if i + 1 < len {
-                val |= (buffer[i + 1] as u32) << 8;
-            }
+            let len = block.len();
+            // Write data in 4-byte chunks
+            for i in (0..len).step_by(4) {
+                // Pack bytes into a 32-bit word, handling potential buffer underrun
+                let mut val: u32 = (block[i] as u32) << 0;
 
-            if i + 2 < len {
-                val |= (buffer[i + 2] as u32) << 16;
-            }
+                if i + 1 < len {
+                    val |= (block[i + 1] as u32) << 8;
+                }
 
-            if i + 3 < len {
-                val |= (buffer[i + 3] as u32) << 24;
-            }
+                if i + 2 < len {
+                    val |= (block[i + 2] as u32) << 16;
+                }
 
-            // Write the 32-bit word to the buffer data register
-            self.write_reg(EMMC_BUF_DATA, val);
+                if i + 3 < len {
+                    val |= (block[i + 3] as u32) << 24;
+                }
+
+                // Write the 32-bit word to the buffer data register
+                self.write_reg(EMMC_BUF_DATA, val);
+            }
         }
 
         // Wait for data transfer to complete
-        self.wait_for_interrupt(EMMC_INT_DATA_END, 1000000)?;
+        self.wait_for_interrupt(EMMC_INT_DATA_END, self.mmc_data_timeout(true, blocks))?;
 
         Ok(())
     }
@@ -567,33 +700,38 @@ impl EMmcHost {
     /// Read data from SD card buffer register
     /// This is a lower-level function used by data transfer operations
     pub fn read_buffer(&self, buffer: &mut [u8]) -> Result<(), SdError> {
-        // Wait until data is available in the controller buffer
-        self.wait_for_interrupt(EMMC_INT_DATA_AVAIL, 100000)?;
+        let blocks = buffer.len().div_ceil(BLOCK_SIZE) as u32;
 
-        // Read data into buffer in 4-byte chunks
-        let len = buffer.len();
-        for i in (0..len).step_by(4) {
-            // Read 32-bit word from buffer data register
-            let val = self.read_reg(EMMC_BUF_DATA);
+        // The controller buffers one block at a time, wait for data
+        // before every block of a multi-block transfer
+        for block in buffer.chunks_mut(BLOCK_SIZE) {
+            self.wait_for_interrupt(EMMC_INT_DATA_AVAIL, self.mmc_data_timeout(false, 1))?;
 
-            // Unpack the 32-bit word into individual bytes, handling buffer boundary
-            buffer[i] = (val & 0xFF) as u8;
+            // Read the block in 4-byte chunks
+            let len = block.len();
+            for i in (0..len).step_by(4) {
+                // Read 32-bit word from buffer data register
+                let val = self.read_reg(EMMC_BUF_DATA);
 
-            if i + 1 < len {
-                buffer[i + 1] = ((val >> 8) & 0xFF) as u8;
-            }
+                // Unpack the 32-bit word into individual bytes, handling buffer boundary
+                block[i] = (val & 0xFF) as u8;
 
-            if i + 2 < len {
-                buffer[i + 2] = ((val >> 16) & 0xFF) as u8;
-            }
+                if i + 1 < len {
+                    block[i + 1] = ((val >> 8) & 0xFF) as u8;
+                }
+
+                if i + 2 < len {
+                    block[i + 2] = ((val >> 16) & 0xFF) as u8;
+                }
 
-            if i + 3 < len {
-                buffer[i + 3] = ((val >> 24) & 0xFF) as u8;
+                if i + 3 < len {
+                    block[i + 3] = ((val >> 24) & 0xFF) as u8;
+                }
             }
         }
 
         // Wait for data transfer to complete
-        self.wait_for_interrupt(EMMC_INT_DATA_END, 100000)?;
+        self.wait_for_interrupt(EMMC_INT_DATA_END, self.mmc_data_timeout(false, blocks))?;
 
         Ok(())
     }
@@ -602,10 +740,9 @@ impl EMmcHost {
     /// Helper function used by data transfer operations
     /// Parameters:
     /// - flag: The interrupt flag to wait for
-    /// - timeout_count: Maximum number of iterations to wait
-    fn wait_for_interrupt(&self, flag: u32, timeout_count: u32) -> Result<(), SdError> {
-        let mut timeout = timeout_count;
-        while timeout > 0 {
+    /// - timeout: Maximum time to wait
+    fn wait_for_interrupt(&self, flag: u32, timeout: Duration) -> Result<(), SdError> {
+        let result = self.wait_for(timeout, || {
             // Read the current interrupt status
             let int_status = self.read_reg(EMMC_NORMAL_INT_STAT);
 
@@ -613,29 +750,79 @@ impl EMmcHost {
             if int_status & flag != 0 {
                 // Clear the flag by writing back to the register
                 self.write_reg16(EMMC_NORMAL_INT_STAT, flag as u16);
-                return Ok(());
+                return Ok(true);
             }
 
-            // Check for any error flags
+            // Check for any error flags, send_command records and resets them
             if int_status & EMMC_INT_ERROR_MASK != 0 {
-                // Clear error flags
-                self.write_reg16(
-                    EMMC_NORMAL_INT_STAT,
-                    (int_status & EMMC_INT_ERROR_MASK) as u16,
-                );
-                // Reset the data circuit
-                self.reset_data()?;
-                return Err(SdError::DataError);
+                return Err(mmc_data_error(self.read_reg16(EMMC_ERROR_INT_STAT)));
             }
 
-            timeout -= 1;
-        }
+            Ok(false)
+        });
 
         // If we reached the timeout limit, return timeout error
-        if timeout == 0 {
-            return Err(SdError::DataTimeout);
+        match result {
+            Err(SdError::Timeout) => Err(SdError::DataTimeout),
+            result => result,
+        }
+    }
+}
+
+/// A transfer buffer of `len` bytes holds exactly `blocks` blocks, at least one
+fn mmc_check_buffer(blocks: u32, len: usize) -> Result<(), SdError> {
+    if blocks == 0 || len != blocks as usize * 512 {
+        return Err(SdError::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// Data error reported by EMMC_ERROR_INT_STAT
+fn mmc_data_error(err_status: u16) -> SdError {
+    if err_status & 0x10 != 0 {
+        SdError::DataTimeout
+    } else if err_status & 0x20 != 0 {
+        SdError::DataCrc
+    } else if err_status & 0x40 != 0 {
+        SdError::DataEndBit
+    } else {
+        SdError::DataError
+    }
+}
+
+impl BlockDevice for EMmcHost {
+    fn num_blocks(&self) -> u64 {
+        self.card.as_ref().map_or(0, |card| card.capacity_blocks)
+    }
+
+    fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<(), SdError> {
+        let blocks = buf_blocks(buf.len(), BLOCK_SIZE)?;
+        let blocks = u32::try_from(blocks).map_err(|_| SdError::InvalidArgument)?;
+
+        self.read_blocks(block_id, blocks, buf)
+    }
+
+    fn write(&self, block_id: u64, buf: &[u8]) -> Result<(), SdError> {
+        let blocks = buf_blocks(buf.len(), BLOCK_SIZE)?;
+        let blocks = u32::try_from(blocks).map_err(|_| SdError::InvalidArgument)?;
+
+        self.write_blocks(block_id, blocks, buf)
+    }
+
+    /// Writes complete synchronously, wait until the card has finished programming
+    fn flush(&self) -> Result<(), SdError> {
+        if self.card.is_none() {
+            return Err(SdError::NoCard);
         }
 
-        Ok(())
+        self.mmc_poll_for_busy(false)
+    }
+
+    fn discard(&self, block_id: u64, blocks: u64) -> Result<(), SdError> {
+        self.discard_blocks(block_id, blocks)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.mmc_check_writable().is_err()
     }
 }
@@ -0,0 +1,111 @@
+use log::info;
+
+use super::{EMmcHost, config::EMmcChipConfig, constant::*};
+
+/// Features the controller reports in EMMC_CAPABILITIES1/2
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostCapabilities {
+    pub bus_8bit: bool,
+    pub high_speed: bool,
+    pub sdma: bool,
+    pub adma2: bool,
+    pub dma_64bit: bool,
+    pub sdr50: bool,
+    pub sdr104: bool,
+    pub ddr50: bool,
+    pub hs400: bool,
+    pub driver_type_a: bool,
+    pub driver_type_c: bool,
+    pub driver_type_d: bool,
+    pub vdd_330: bool,
+    pub vdd_300: bool,
+    pub vdd_180: bool,
+}
+
+impl HostCapabilities {
+    /// Decode the capability registers of a controller with spec `version`
+    pub fn from_regs(caps1: u32, caps2: u32, version: u16) -> Self {
+        // Capabilities 2 and the 8-bit bit only exist from spec 3.00 on
+        let v3 = (version & EMMC_SPEC_VER_MASK) >= EMMC_SPEC_300;
+        let caps2 = if v3 { caps2 } else { 0 };
+
+        Self {
+            bus_8bit: v3 && (caps1 & EMMC_CAN_DO_8BIT) != 0,
+            high_speed: (caps1 & EMMC_CAN_DO_HISPD) != 0,
+            sdma: (caps1 & EMMC_CAN_DO_SDMA) != 0,
+            adma2: (caps1 & EMMC_CAN_DO_ADMA2) != 0,
+            dma_64bit: (caps1 & EMMC_CAN_64BIT) != 0,
+            sdr50: (caps2 & EMMC_SUPPORT_SDR50) != 0,
+            sdr104: (caps2 & EMMC_SUPPORT_SDR104) != 0,
+            ddr50: (caps2 & EMMC_SUPPORT_DDR50) != 0,
+            hs400: (caps2 & EMMC_SUPPORT_HS400) != 0,
+            driver_type_a: (caps2 & EMMC_DRIVER_TYPE_A) != 0,
+            driver_type_c: (caps2 & EMMC_DRIVER_TYPE_C) != 0,
+            driver_type_d: (caps2 & EMMC_DRIVER_TYPE_D) != 0,
+            vdd_330: (caps1 & EMMC_CAN_VDD_330) != 0,
+            vdd_300: (caps1 & EMMC_CAN_VDD_300) != 0,
+            vdd_180: (caps1 & EMMC_CAN_VDD_180) != 0,
+        }
+    }
+
+    /// `MMC_MODE_*` bits usable with this controller on a board described by `config`
+    pub fn mmc_modes(&self, config: &EMmcChipConfig) -> u32 {
+        let mut modes = 0;
+
+        // Bus width is limited by the lines actually wired to the card
+        if config.bus_width >= 4 {
+            modes |= MMC_MODE_4BIT;
+        }
+        if config.bus_width >= 8 && self.bus_8bit {
+            modes |= MMC_MODE_8BIT;
+        }
+
+        if self.high_speed && config.cap_mmc_highspeed {
+            modes |= MMC_MODE_HS | MMC_MODE_HS_52MHZ;
+        }
+
+        // MMC_MODE_DDR_52MHZ is left out even with `ddr50` and `mmc_ddr_1_8v`
+        // until `mmc_change_freq` can select HS-DDR
+
+        // HS200 needs the same 200MHz tuned bus as SDR104
+        let hs200 = self.sdr104 && config.mmc_hs200_1_8v;
+        if hs200 {
+            modes |= MMC_MODE_HS200;
+        }
+
+        // HS400 only has a non-standard capability bit, so an 8-bit HS200
+        // capable controller is assumed to handle it
+        if hs200 && (modes & MMC_MODE_8BIT) != 0 && config.mmc_hs400_1_8v {
+            modes |= MMC_MODE_HS400;
+
+            if config.mmc_hs400_enhanced_strobe {
+                modes |= MMC_MODE_HS400ES;
+            }
+        }
+
+        modes
+    }
+}
+
+impl EMmcHost {
+    /// Controller capabilities read during `init`
+    pub fn capabilities(&self) -> HostCapabilities {
+        self.capabilities
+    }
+
+    /// `MMC_MODE_*` bits negotiated between controller and board
+    pub fn host_caps(&self) -> u32 {
+        self.host_caps
+    }
+
+    /// Read the capability registers and intersect them with the board configuration
+    pub(crate) fn mmc_negotiate_caps(&mut self, caps1: u32, caps2: u32) {
+        self.capabilities = HostCapabilities::from_regs(caps1, caps2, self.version);
+        self.host_caps = self.capabilities.mmc_modes(&self.config);
+
+        info!(
+            "Host capabilities: {:?}, modes {:#x}",
+            self.capabilities, self.host_caps
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use spin::Once;
+use alloc::boxed::Box;
+
+use super::EMmcHost;
+use crate::err::SdError;
 
 #[derive(Debug, Clone, Copy)]
 pub enum ClkError {
@@ -9,33 +12,40 @@ pub enum ClkError {
     NotInitialized,
 }
 
-pub trait Clk {
+/// Source of the controller input clock, typically a CRU gate/divider.
+///
+/// Every host owns its own provider, so controllers fed by different
+/// clocks can coexist.
+pub trait Clk: Send {
     fn emmc_get_clk(&self) -> Result<u64, ClkError>;
     fn emmc_set_clk(&self, rate: u64) -> Result<u64, ClkError>;
 }
 
-static INIT: Once = Once::new();
-static mut GLOBAL_CLK_INSTANCE: Option<&'static dyn Clk> = None;
-
-pub fn init_global_clk(clk: &'static dyn Clk) {
-    INIT.call_once(|| unsafe {
-        GLOBAL_CLK_INSTANCE = Some(clk);
-    });
+impl core::fmt::Debug for dyn Clk {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Clk")
+    }
 }
 
-pub fn global_clk() -> Result<&'static dyn Clk, ClkError> {
-    unsafe {
-        match GLOBAL_CLK_INSTANCE {
-            Some(instance) => Ok(instance),
-            None => Err(ClkError::NotInitialized),
-        }
+impl EMmcHost {
+    /// Use `clk` as the input clock provider of this host
+    pub fn with_clk(mut self, clk: Box<dyn Clk>) -> Self {
+        self.clk = Some(clk);
+        self
     }
-}
 
-pub fn emmc_set_clk(rate: u64) -> Result<u64, ClkError> {
-    global_clk()?.emmc_set_clk(rate)
-}
+    pub fn set_clk(&mut self, clk: Box<dyn Clk>) {
+        self.clk = Some(clk);
+    }
+
+    /// Card clock in Hz actually produced by the last clock change, 0 when stopped
+    pub fn actual_clock(&self) -> u32 {
+        self.clock
+    }
 
-pub fn emmc_get_clk() -> Result<u64, ClkError> {
-    global_clk()?.emmc_get_clk()
+    /// Ask the provider for `rate` and return the input clock it achieved
+    pub(crate) fn mmc_set_input_clk(&self, rate: u64) -> Result<u64, SdError> {
+        let clk = self.clk.as_ref().ok_or(ClkError::NotInitialized)?;
+        Ok(clk.emmc_set_clk(rate)?)
+    }
 }
@@ -1,16 +1,19 @@
-#[cfg(feature = "dma")]
-use dma_api::DVec;
+use alloc::{boxed::Box, vec::Vec};
+use core::time::Duration;
 use log::{debug, info, trace};
 
-use crate::{delay_us, emmc::CardType, err::SdError};
+use crate::{
+    emmc::{CardState, CardStatus, CardType},
+    err::{CommandError, SdError},
+};
 
-use super::{EMmcHost, block::DataBuffer, constant::*};
+use super::{
+    EMMC_BUSY_POLL_US, EMMC_LINE_RESET_TIMEOUT, EMmcHost, block::DataBuffer, constant::*,
+    timeout::EMMC_TIMEOUT_CONTROL_MAX,
+};
 
 #[allow(dead_code)]
-const EMMC_DEFAULT_BOUNDARY_ARG: u16 = 7;
-
-const CMD_DEFAULT_TIMEOUT: u32 = 100;
-const CMD_MAX_TIMEOUT: u32 = 500;
+pub(crate) const EMMC_DEFAULT_BOUNDARY_ARG: u16 = 7;
 
 #[derive(Debug)]
 pub struct EMmcCommand {
@@ -21,6 +24,9 @@ pub struct EMmcCommand {
     pub data_dir_read: bool,
     pub block_size: u16,
     pub block_count: u16,
+    pub buffer_offset: usize,
+    /// Issue as an abort command, ending the transfer in progress
+    pub abort: bool,
 }
 
 impl EMmcCommand {
@@ -33,6 +39,8 @@ impl EMmcCommand {
             data_dir_read: true,
             block_size: 0,
             block_count: 0,
+            buffer_offset: 0,
+            abort: false,
         }
     }
 
@@ -43,6 +51,17 @@ impl EMmcCommand {
         self.block_count = block_count;
         self
     }
+
+    /// Start the DMA transfer `offset` bytes into the data buffer
+    pub fn with_buffer_offset(mut self, offset: usize) -> Self {
+        self.buffer_offset = offset;
+        self
+    }
+
+    pub fn with_abort(mut self) -> Self {
+        self.abort = true;
+        self
+    }
 }
 
 pub struct SdResponse {
@@ -94,7 +113,11 @@ impl EMmcHost {
         cmd: &EMmcCommand,
         mut data_buffer: Option<DataBuffer>,
     ) -> Result<(), SdError> {
-        let mut cmd_timeout = CMD_DEFAULT_TIMEOUT;
+        // Requests in flight when the card is pulled fail here
+        if !self.is_card_present() {
+            return Err(SdError::NoCard);
+        }
+        self.mmc_check_cmd_allowed(cmd)?;
 
         // Check if command or data line is busy
         let mut mask = EMMC_CMD_INHIBIT;
@@ -107,29 +130,16 @@ impl EMmcHost {
             mask &= !EMMC_DATA_INHIBIT;
         }
 
-        // Wait using dynamically adjusted timeout
-        let mut time: u32 = 0;
-        while (self.read_reg(EMMC_PRESENT_STATE) & mask) != 0 {
-            if time >= cmd_timeout {
-                info!("MMC: busy timeout");
-
-                // If timeout can be increased, double the timeout and continue
-                if 2 * cmd_timeout <= CMD_MAX_TIMEOUT {
-                    cmd_timeout += cmd_timeout;
-                    info!("timeout increasing to: {} ms.", cmd_timeout);
-                    self.write_reg16(EMMC_NORMAL_INT_STAT, 0xFFFF);
-                } else {
-                    info!("timeout.");
-                    // Do not return an error, attempt to continue sending the command
-                    break;
-                }
-            }
-            time += 1;
-            delay_us(1000);
+        let inhibit = self.wait_for(self.timeouts.inhibit, || {
+            Ok((self.read_reg(EMMC_PRESENT_STATE) & mask) == 0)
+        });
+        if inhibit.is_err() {
+            // Do not return an error, attempt to continue sending the command
+            info!("MMC: busy timeout");
         }
 
-        // Clear all interrupt statuses
-        self.write_reg16(EMMC_NORMAL_INT_STAT, 0xFFFF);
+        // Clear all interrupt statuses, card detect is left to poll_card_detect
+        self.write_reg16(EMMC_NORMAL_INT_STAT, !EMMC_INT_CARD_DETECT as u16);
         self.write_reg16(EMMC_ERROR_INT_STAT, 0xFFFF);
 
         let mut int_mask = EMMC_INT_RESPONSE as u16;
@@ -141,7 +151,10 @@ impl EMmcHost {
 
         // Set data transfer-related registers
         if cmd.data_present {
-            self.write_reg8(EMMC_TIMEOUT_CONTROL, 0xe);
+            self.write_reg8(
+                EMMC_TIMEOUT_CONTROL,
+                self.mmc_timeout_control(!cmd.data_dir_read),
+            );
 
             let mut mode = EMMC_TRNS_BLK_CNT_EN;
 
@@ -153,57 +166,38 @@ impl EMmcHost {
                 mode |= EMMC_TRNS_READ;
             }
 
-            #[cfg(feature = "dma")]
-            {
-                // Configure transfer mode
-                self.write_reg16(EMMC_XFER_MODE, mode);
+            // The buffer type decides between SDMA and PIO for this command
+            let mut block_size = cmd.block_size & 0xFFF;
+            match data_buffer {
+                Some(DataBuffer::DmaRead(ref read_buf)) if cmd.data_dir_read => {
+                    let ptr = read_buf.bus_addr() as usize + cmd.buffer_offset;
 
-                match data_buffer {
-                    Some(DataBuffer::Read(ref read_buf)) if cmd.data_dir_read => {
-                        let ptr = read_buf.bus_addr() as usize;
+                    debug!("Read buffer address: {:#x}", ptr);
+                    self.write_reg(EMMC_SDMASA, ptr as u32);
+                }
+                Some(DataBuffer::DmaWrite(write_buf)) if !cmd.data_dir_read => {
+                    let ptr = write_buf.bus_addr() as usize + cmd.buffer_offset;
 
-                        debug!("Read buffer address: {:#x}", ptr);
-                        self.write_reg(EMMC_SDMASA, ptr as u32);
-                    }
-                    Some(DataBuffer::Write(write_buf)) if !cmd.data_dir_read => {
-                        let ptr = write_buf.as_ptr() as usize;
-                        let start_addr = ptr as u32;
-                        self.write_reg(EMMC_SDMASA, start_addr);
-                    }
-                    _ => return Err(SdError::InvalidArgument),
+                    debug!("Write buffer address: {:#x}", ptr);
+                    self.write_reg(EMMC_SDMASA, ptr as u32);
                 }
+                Some(DataBuffer::Read(_)) if cmd.data_dir_read => {}
+                Some(DataBuffer::Write(_)) if !cmd.data_dir_read => {}
+                _ => return Err(SdError::InvalidArgument),
+            }
 
+            if data_buffer.as_ref().is_some_and(DataBuffer::is_dma) {
                 mode |= EMMC_TRNS_DMA;
-
-                // Set block size and count
-                self.write_reg16(
-                    EMMC_BLOCK_SIZE,
-                    (((EMMC_DEFAULT_BOUNDARY_ARG & 0x7) << 12) | (cmd.block_size & 0xFFF))
-                        .try_into()
-                        .unwrap(),
-                );
-                self.write_reg16(EMMC_BLOCK_COUNT, cmd.block_count);
-                self.write_reg16(EMMC_XFER_MODE, mode);
+                block_size |= (EMMC_DEFAULT_BOUNDARY_ARG & 0x7) << 12;
             }
 
-            #[cfg(feature = "pio")]
-            {
-                self.write_reg16(
-                    EMMC_BLOCK_SIZE,
-                    (cmd.block_size & 0xFFF).try_into().unwrap(),
-                );
-                self.write_reg16(EMMC_BLOCK_COUNT, cmd.block_count);
-
-                self.write_reg16(EMMC_XFER_MODE, mode);
-                match data_buffer {
-                    Some(DataBuffer::Read(_)) if cmd.data_dir_read => {}
-                    Some(DataBuffer::Write(_)) if !cmd.data_dir_read => {}
-                    _ => return Err(SdError::InvalidArgument),
-                }
-            }
+            // Set block size and count
+            self.write_reg16(EMMC_BLOCK_SIZE, block_size);
+            self.write_reg16(EMMC_BLOCK_COUNT, cmd.block_count);
+            self.write_reg16(EMMC_XFER_MODE, mode);
         } else if cmd.resp_type & MMC_RSP_BUSY != 0 {
-            // For commands with BUSY but no data, still set timeout control
-            self.write_reg8(EMMC_TIMEOUT_CONTROL, 0xe);
+            // Busy-only commands are bounded in software, give DAT0 the longest count
+            self.write_reg8(EMMC_TIMEOUT_CONTROL, EMMC_TIMEOUT_CONTROL_MAX);
         }
 
         // Set parameters
@@ -241,46 +235,36 @@ impl EMmcHost {
             command |= EMMC_CMD_DATA;
         }
 
+        if cmd.abort {
+            command |= EMMC_CMD_ABORTCMD as u16;
+        }
+
         trace!(
             "Sending command: opcode={:#x}, arg={:#x}, resp_type={:#x}, command={:#x}",
             cmd.opcode, cmd.arg, cmd.resp_type, command
         );
 
         // Special command handling
-        let mut timeout_val = if cmd.opcode == MMC_GO_IDLE_STATE || cmd.opcode == MMC_SEND_OP_COND {
-            CMD_MAX_TIMEOUT
+        let cmd_timeout = if cmd.opcode == MMC_GO_IDLE_STATE || cmd.opcode == MMC_SEND_OP_COND {
+            self.timeouts.cmd_slow
         } else {
-            CMD_DEFAULT_TIMEOUT
+            self.timeouts.cmd
         };
 
         // Send the command
         self.write_reg16(EMMC_COMMAND, command);
 
-        // Wait for command completion
-        let mut status: u16;
-        loop {
+        // Wait for command completion or an error
+        let mut status: u16 = 0;
+        self.wait_for(cmd_timeout, || {
             status = self.read_reg16(EMMC_NORMAL_INT_STAT);
             trace!("Response Status: {:#b}", status);
-
-            // Check for errors
-            if status & EMMC_INT_ERROR as u16 != 0 {
-                break;
-            }
-
-            // Check for response completion
-            if (status & int_mask) == int_mask {
-                break;
-            }
-
-            // Check for timeout
-            if timeout_val == 0 {
-                info!("Timeout for status update!");
-                return Err(SdError::Timeout);
-            }
-
-            timeout_val -= 1;
-            delay_us(100);
-        }
+            Ok(status & EMMC_INT_ERROR as u16 != 0 || (status & int_mask) == int_mask)
+        })
+        .map_err(|e| {
+            info!("Timeout for status update!");
+            self.mmc_command_error(cmd, e, None)
+        })?;
 
         // Process command completion
         if (status & (EMMC_INT_ERROR as u16 | int_mask)) == int_mask {
@@ -304,14 +288,8 @@ impl EMmcHost {
                 status, err_status
             );
 
-            // Reset command and data lines
-            self.reset_cmd()?;
-            if cmd.data_present {
-                self.reset_data()?;
-            }
-
             // Map specific error types
-            let err = if err_status & 0x1 != 0 {
+            let kind = if err_status & 0x1 != 0 {
                 SdError::Timeout
             } else if err_status & 0x2 != 0 {
                 SdError::Crc
@@ -330,35 +308,72 @@ impl EMmcHost {
             } else {
                 SdError::CommandError
             };
+            // Capture the status registers before the resets clear them
+            let err = self.mmc_command_error(cmd, kind, None);
+
+            // Reset command and data lines
+            self.reset_cmd()?;
+            if cmd.data_present {
+                self.reset_data()?;
+            }
 
             return Err(err);
         }
 
+        // Keep R1 for status checking, the line resets below may clear it
+        let response = self.read_reg(EMMC_RESPONSE);
+
         // Process data transfer part
         if cmd.data_present {
             trace!("Data transfer: cmd.data_present={}", cmd.data_present);
-            if let Some(buffer) = &mut data_buffer {
-                #[cfg(feature = "dma")]
-                self.transfer_data_by_dma()?;
-
-                #[cfg(feature = "pio")]
-                match buffer {
-                    DataBuffer::Read(buf) => self.read_buffer(buf)?,
-                    DataBuffer::Write(buf) => self.write_buffer(buf)?,
-                }
-            } else {
-                return Err(SdError::InvalidArgument);
+            let result = match &mut data_buffer {
+                Some(DataBuffer::DmaRead(_) | DataBuffer::DmaWrite(_)) => self
+                    .transfer_data_by_dma(
+                        self.mmc_data_timeout(!cmd.data_dir_read, cmd.block_count as u32),
+                    ),
+                Some(DataBuffer::Read(buf)) => self.read_buffer(buf),
+                Some(DataBuffer::Write(buf)) => self.write_buffer(buf),
+                None => return Err(SdError::InvalidArgument),
+            };
+
+            if let Err(kind) = result {
+                let err = self.mmc_command_error(cmd, kind, Some(response));
+                // Reset the data circuit to recover from the error
+                self.reset_data()?;
+                return Err(err);
             }
         }
 
-        // Clear all interrupt statuses
-        self.write_reg16(EMMC_NORMAL_INT_STAT, 0xFFFF);
+        // Clear all interrupt statuses, card detect is left to poll_card_detect
+        self.write_reg16(EMMC_NORMAL_INT_STAT, !EMMC_INT_CARD_DETECT as u16);
         self.write_reg16(EMMC_ERROR_INT_STAT, 0xFFFF);
 
         self.reset(EMMC_RESET_CMD)?;
         self.reset(EMMC_RESET_DATA)?;
 
-        Ok(())
+        self.mmc_track_card_state(cmd, response);
+        self.mmc_check_r1(cmd, response)
+            .map_err(|e| self.mmc_command_error(cmd, e, Some(response)))
+    }
+
+    /// Attach `cmd` and the current controller status to `kind`. Call before
+    /// resetting the lines, the resets clear part of the status.
+    fn mmc_command_error(&self, cmd: &EMmcCommand, kind: SdError, r1: Option<u32>) -> SdError {
+        SdError::Command(Box::new(CommandError {
+            kind,
+            opcode: cmd.opcode,
+            arg: cmd.arg,
+            blocks: if cmd.data_present { cmd.block_count } else { 0 },
+            timing: self
+                .card
+                .as_ref()
+                .map_or(MMC_TIMING_LEGACY, |card| card.timing),
+            normal_status: self.read_reg16(EMMC_NORMAL_INT_STAT),
+            error_status: self.read_reg16(EMMC_ERROR_INT_STAT),
+            auto_cmd_status: self.read_reg16(EMMC_AUTO_CMD_STAT),
+            adma_status: self.read_reg8(EMMC_ADMA_ERR_STAT),
+            r1: r1.map(CardStatus),
+        }))
     }
 
     // Reset command line
@@ -366,16 +381,9 @@ impl EMmcHost {
         self.write_reg8(EMMC_SOFTWARE_RESET, EMMC_RESET_CMD);
 
         // Wait for reset to complete
-        let mut timeout = 100;
-        while (self.read_reg8(EMMC_SOFTWARE_RESET) & EMMC_RESET_CMD) != 0 {
-            if timeout == 0 {
-                return Err(SdError::Timeout);
-            }
-            timeout -= 1;
-            delay_us(1000);
-        }
-
-        Ok(())
+        self.wait_for(EMMC_LINE_RESET_TIMEOUT, || {
+            Ok((self.read_reg8(EMMC_SOFTWARE_RESET) & EMMC_RESET_CMD) == 0)
+        })
     }
 
     // Reset data line
@@ -383,16 +391,9 @@ impl EMmcHost {
         self.write_reg8(EMMC_SOFTWARE_RESET, EMMC_RESET_DATA);
 
         // Wait for reset to complete
-        let mut timeout = 100;
-        while (self.read_reg8(EMMC_SOFTWARE_RESET) & EMMC_RESET_DATA) != 0 {
-            if timeout == 0 {
-                return Err(SdError::Timeout);
-            }
-            timeout -= 1;
-            delay_us(1000);
-        }
-
-        Ok(())
+        self.wait_for(EMMC_LINE_RESET_TIMEOUT, || {
+            Ok((self.read_reg8(EMMC_SOFTWARE_RESET) & EMMC_RESET_DATA) == 0)
+        })
     }
 
     // Get response from the last command
@@ -411,7 +412,7 @@ impl EMmcHost {
         let cmd = EMmcCommand::new(MMC_GO_IDLE_STATE, 0, MMC_RSP_NONE);
         self.send_command(&cmd, None)?;
 
-        delay_us(10000);
+        self.delay_us(10000)?;
 
         info!("eMMC reset complete");
         Ok(())
@@ -423,7 +424,7 @@ impl EMmcHost {
 
         let mut cmd = EMmcCommand::new(MMC_SEND_OP_COND, ocr, MMC_RSP_R3);
         self.send_command(&cmd, None)?;
-        delay_us(10000);
+        self.delay_us(10000)?;
 
         // Get response and store it
         let mut card_ocr = self.get_response().as_r3();
@@ -464,6 +465,8 @@ impl EMmcHost {
                     if (resp & ocr_hcs) != 0 {
                         card.card_type = CardType::MmcHc;
                         card.state |= MMC_STATE_HIGHCAPACITY;
+                    } else {
+                        card.card_type = CardType::Mmc;
                     }
                 }
             }
@@ -471,7 +474,7 @@ impl EMmcHost {
             if !ready {
                 retry -= 1;
                 // Delay between retries
-                delay_us(1000);
+                self.delay_us(1000)?;
             }
         }
 
@@ -481,7 +484,7 @@ impl EMmcHost {
             return Err(SdError::UnsupportedCard);
         }
 
-        delay_us(1000);
+        self.delay_us(1000)?;
 
         debug!(
             "Clock control before CMD2: 0x{:x}, stable: {}",
@@ -534,8 +537,8 @@ impl EMmcHost {
         Ok(card.csd)
     }
 
-    #[cfg(feature = "dma")]
-    pub fn mmc_send_ext_csd(&mut self, ext_csd: &mut DVec<u8>) -> Result<(), SdError> {
+    /// CMD8, always through PIO as a DMA buffer costs more than it saves here
+    pub fn mmc_send_ext_csd(&mut self, ext_csd: &mut [u8; 512]) -> Result<(), SdError> {
         let cmd = EMmcCommand::new(MMC_SEND_EXT_CSD, 0, MMC_RSP_R1).with_data(
             MMC_MAX_BLOCK_LEN as u16,
             1,
@@ -550,25 +553,32 @@ impl EMmcHost {
         Ok(())
     }
 
-    #[cfg(feature = "pio")]
-    pub fn mmc_send_ext_csd(&mut self, ext_csd: &mut [u8; 512]) -> Result<(), SdError> {
-        let cmd = EMmcCommand::new(MMC_SEND_EXT_CSD, 0, MMC_RSP_R1).with_data(
-            MMC_MAX_BLOCK_LEN as u16,
-            1,
-            true,
-        );
-
-        self.send_command(&cmd, Some(DataBuffer::Read(ext_csd)))?;
-
-        // debug!("CMD8: {:#x}",self.get_response().as_r1());
-        // debug!("EXT_CSD read successfully, rev: {}", ext_csd[EXT_CSD_REV as usize]);
+    /// Read the EXT_CSD register into an owned buffer
+    pub fn mmc_read_ext_csd(&mut self) -> Result<Vec<u8>, SdError> {
+        let mut ext_csd: [u8; 512] = [0; 512];
+        self.mmc_send_ext_csd(&mut ext_csd)?;
 
-        Ok(())
+        Ok(ext_csd.to_vec())
     }
 
     pub fn mmc_poll_for_busy(&self, send_status: bool) -> Result<(), SdError> {
+        self.mmc_poll_for_busy_timeout(send_status, self.timeouts.busy, None)
+    }
+
+    /// Wait for the card to leave the busy state, giving up after `timeout`.
+    ///
+    /// `cancel` is checked once per poll interval. When it returns true the
+    /// ongoing operation is interrupted with HPI (if enabled) and
+    /// `SdError::Cancelled` is returned. Without HPI the card may still be
+    /// busy when the wait is cancelled.
+    pub fn mmc_poll_for_busy_timeout(
+        &self,
+        send_status: bool,
+        timeout: Duration,
+        cancel: Option<&dyn Fn() -> bool>,
+    ) -> Result<(), SdError> {
         let mut busy = true;
-        let mut timeout = 1000;
+        let deadline = self.deadline(timeout)?;
 
         // 轮询等待卡忙状态结束
         while busy {
@@ -578,27 +588,35 @@ impl EMmcHost {
                     self.card.as_ref().unwrap().rca << 16,
                     MMC_RSP_R1,
                 );
+                // R1 errors such as SWITCH_ERROR are reported by send_command
                 self.send_command(&cmd, None)?;
-                let response = self.get_response().as_r1();
-                trace!("cmd_d {:#x}", response);
+                let status = CardStatus::from(self.get_response().as_r1());
+                trace!("cmd_d {:?}", status);
 
-                if response & MMC_STATUS_SWITCH_ERROR != 0 {
-                    return Err(SdError::BadMessage);
-                }
-                busy = (response & MMC_STATUS_CURR_STATE) == MMC_STATE_PRG;
+                busy = status.state() == CardState::Prg;
                 if !busy {
                     break;
                 }
             } else {
                 busy = self.mmc_card_busy();
+                if !busy && self.card_state() == Some(CardState::Prg) {
+                    self.mmc_set_card_state(CardState::Tran);
+                }
+            }
+
+            if busy && cancel.is_some_and(|cancel| cancel()) {
+                debug!("Busy wait cancelled");
+                if self.card.as_ref().is_some_and(|card| card.hpi_en) {
+                    self.mmc_send_hpi()?;
+                }
+                return Err(SdError::Cancelled);
             }
 
-            if timeout == 0 && busy {
+            if busy && deadline.expired() {
                 return Err(SdError::Timeout);
             }
 
-            timeout -= 1;
-            delay_us(1000);
+            self.delay_us(EMMC_BUSY_POLL_US)?;
         }
 
         Ok(())
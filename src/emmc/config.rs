@@ -8,6 +8,23 @@ pub struct EMmcChipConfig {
     pub hs400_cmd_tap: u8,
     pub hs400_strbin_tap: u8,
     pub _ddr50_strbin_delay_num: u8,
+
+    // Board constraints
+    /// Number of data lines wired to the card: 1, 4 or 8
+    pub bus_width: u8,
+    /// Highest bus clock in Hz, 0 for no limit
+    pub max_frequency: u32,
+    /// Card is soldered down, card detect is meaningless
+    pub non_removable: bool,
+    /// Card detect line is not usable, poll for the card instead
+    pub broken_cd: bool,
+    pub no_sd: bool,
+    pub no_sdio: bool,
+    pub cap_mmc_highspeed: bool,
+    pub mmc_ddr_1_8v: bool,
+    pub mmc_hs200_1_8v: bool,
+    pub mmc_hs400_1_8v: bool,
+    pub mmc_hs400_enhanced_strobe: bool,
 }
 
 impl EMmcChipConfig {
@@ -19,6 +36,23 @@ impl EMmcChipConfig {
             hs400_cmd_tap: 8,
             hs400_strbin_tap: 3,
             _ddr50_strbin_delay_num: 16,
+            bus_width: 8,
+            max_frequency: 0,
+            non_removable: true,
+            broken_cd: false,
+            no_sd: true,
+            no_sdio: true,
+            cap_mmc_highspeed: true,
+            mmc_ddr_1_8v: false,
+            mmc_hs200_1_8v: true,
+            mmc_hs400_1_8v: false,
+            mmc_hs400_enhanced_strobe: false,
         }
     }
 }
+
+impl Default for EMmcChipConfig {
+    fn default() -> Self {
+        Self::rk3568_config()
+    }
+}
@@ -163,6 +163,7 @@ pub const EMMC_INT_ERROR_MASK: u32 = 0xFFFF8000;
 
 pub const EMMC_INT_CMD_MASK: u32 =
     EMMC_INT_RESPONSE | EMMC_INT_TIMEOUT | EMMC_INT_CRC | EMMC_INT_END_BIT | EMMC_INT_INDEX;
+pub const EMMC_INT_CARD_DETECT: u32 = EMMC_INT_CARD_INSERT | EMMC_INT_CARD_REMOVE;
 pub const EMMC_INT_DATA_MASK: u32 = EMMC_INT_DATA_END
     | EMMC_INT_DMA_END
     | EMMC_INT_DATA_AVAIL
@@ -191,6 +192,15 @@ pub const EMMC_CAN_VDD_300: u32 = 1 << 25;
 pub const EMMC_CAN_VDD_180: u32 = 1 << 26;
 pub const EMMC_CAN_64BIT: u32 = 1 << 28;
 
+// EMMC capabilities 2 flags
+pub const EMMC_SUPPORT_SDR50: u32 = 1 << 0;
+pub const EMMC_SUPPORT_SDR104: u32 = 1 << 1;
+pub const EMMC_SUPPORT_DDR50: u32 = 1 << 2;
+pub const EMMC_DRIVER_TYPE_A: u32 = 1 << 4;
+pub const EMMC_DRIVER_TYPE_C: u32 = 1 << 5;
+pub const EMMC_DRIVER_TYPE_D: u32 = 1 << 6;
+pub const EMMC_SUPPORT_HS400: u32 = 1 << 31; // Non-standard
+
 // SD/MMC Command definitions
 // Basic commands (class 0 and class 1)
 pub const MMC_GO_IDLE_STATE: u8 = 0;
@@ -235,6 +245,10 @@ pub const MMC_EARSE_GROUP_START: u8 = 35;
 pub const MMC_EARSE_GROUP_END: u8 = 36;
 pub const MMC_ERASE: u8 = 38;
 
+// CMD38 arguments
+pub const MMC_ERASE_ARG: u32 = 0x00000000;
+pub const MMC_TRIM_ARG: u32 = 0x00000001;
+
 // Table 55 — I/O mode commands (class 9)
 pub const MMC_FAST_IO: u8 = 39;
 pub const MMC_GO_IRQ_STATE: u8 = 40;
@@ -257,6 +271,10 @@ pub const MMC_EXECUTE_READ_TASK: u8 = 46;
 pub const MMC_EXECUTE_WRITE_TASK: u8 = 47;
 pub const MMC_CMDQ_TASK_MGMT: u8 = 48;
 
+// SD specific commands with R6/R7 responses
+pub const SD_SEND_RELATIVE_ADDR: u8 = 3;
+pub const SD_SEND_IF_COND: u8 = 8;
+
 // Response types
 pub const MMC_RSP_PRESENT: u32 = 1 << 0;
 pub const MMC_RSP_136: u32 = 1 << 1; // 136-bit response
@@ -331,6 +349,8 @@ pub const DLL_CMDOUT_BOTH_CLK_EDGE: u32 = 1 << 30;
 // HS400模式控制
 pub const DWCMSHC_CTRL_HS400: u16 = 0x7;
 pub const DWCMSHC_CARD_IS_EMMC: u32 = 1 << 0;
+pub const DWCMSHC_EMMC_RST_N: u32 = 1 << 2;
+pub const DWCMSHC_EMMC_RST_N_OE: u32 = 1 << 3;
 pub const DWCMSHC_ENHANCED_STROBE: u32 = 1 << 8;
 
 // 芯片特性标志
@@ -417,10 +437,45 @@ pub const MMC_SWITCH_MODE_CLEAR_BITS: u32 = 0x02; /* Clear bits in EXT_CSD byte
 pub const MMC_SWITCH_MODE_WRITE_BYTE: u32 = 0x03; /* Set target byte to value */
 
 pub const MMC_STATUS_MASK: u32 = !0x0206BF7F;
+pub const MMC_STATUS_APP_CMD: u32 = 1 << 5;
+pub const MMC_STATUS_EXCEPTION_EVENT: u32 = 1 << 6;
 pub const MMC_STATUS_SWITCH_ERROR: u32 = 1 << 7;
 pub const MMC_STATUS_RDY_FOR_DATA: u32 = 1 << 8;
 pub const MMC_STATUS_CURR_STATE: u32 = 0xf << 9;
+pub const MMC_STATUS_ERASE_RESET: u32 = 1 << 13;
+pub const MMC_STATUS_CARD_ECC_DISABLED: u32 = 1 << 14;
+pub const MMC_STATUS_WP_ERASE_SKIP: u32 = 1 << 15;
+pub const MMC_STATUS_CID_CSD_OVERWRITE: u32 = 1 << 16;
 pub const MMC_STATUS_ERROR: u32 = 1 << 19;
+pub const MMC_STATUS_CC_ERROR: u32 = 1 << 20;
+pub const MMC_STATUS_CARD_ECC_FAILED: u32 = 1 << 21;
+pub const MMC_STATUS_ILLEGAL_COMMAND: u32 = 1 << 22;
+pub const MMC_STATUS_COM_CRC_ERROR: u32 = 1 << 23;
+pub const MMC_STATUS_LOCK_UNLOCK_FAILED: u32 = 1 << 24;
+pub const MMC_STATUS_CARD_IS_LOCKED: u32 = 1 << 25;
+pub const MMC_STATUS_WP_VIOLATION: u32 = 1 << 26;
+pub const MMC_STATUS_ERASE_PARAM: u32 = 1 << 27;
+pub const MMC_STATUS_ERASE_SEQ_ERROR: u32 = 1 << 28;
+pub const MMC_STATUS_BLOCK_LEN_ERROR: u32 = 1 << 29;
+pub const MMC_STATUS_ADDRESS_MISALIGN: u32 = 1 << 30;
+pub const MMC_STATUS_OUT_OF_RANGE: u32 = 1 << 31;
+
+/* R1 bits that report a failed command */
+pub const MMC_STATUS_ERROR_BITS: u32 = MMC_STATUS_OUT_OF_RANGE
+    | MMC_STATUS_ADDRESS_MISALIGN
+    | MMC_STATUS_BLOCK_LEN_ERROR
+    | MMC_STATUS_ERASE_SEQ_ERROR
+    | MMC_STATUS_ERASE_PARAM
+    | MMC_STATUS_WP_VIOLATION
+    | MMC_STATUS_LOCK_UNLOCK_FAILED
+    | MMC_STATUS_COM_CRC_ERROR
+    | MMC_STATUS_ILLEGAL_COMMAND
+    | MMC_STATUS_CARD_ECC_FAILED
+    | MMC_STATUS_CC_ERROR
+    | MMC_STATUS_ERROR
+    | MMC_STATUS_CID_CSD_OVERWRITE
+    | MMC_STATUS_WP_ERASE_SKIP
+    | MMC_STATUS_SWITCH_ERROR;
 
 pub const MMC_STATE_PRG: u32 = 7 << 9;
 
@@ -434,6 +489,9 @@ pub const OCR_HCS: u32 = 0x40000000;
 pub const OCR_VOLTAGE_MAS: u32 = 0x007FFF80;
 pub const OCR_ACCESS_MODE: u32 = 0x60000000;
 
+/* Maximum blocks in one transfer, limited by the 16-bit BLOCK_COUNT register */
+pub const MMC_MAX_BLOCK_COUNT: u32 = 0xFFFF;
+
 /* Maximum block size for MMC */
 pub const MMC_MAX_BLOCK_LEN: u32 = 512;
 
@@ -464,6 +522,9 @@ pub const EXT_CSD_BUS_WIDTH_STROBE: u8 = 1 << 7; /* Enhanced strobe mode */
 /* divided by 10 to be nice to platforms without floating point */
 pub const FBASE: [usize; 4] = [10000, 100000, 1000000, 10000000];
 
+/* TAAC time units in nanoseconds, the mantissa shares MULTIPLIERS */
+pub const TAAC_UNIT_NS: [u32; 8] = [1, 10, 100, 1000, 10000, 100000, 1000000, 10000000];
+
 /* Multiplier values for TRAN_SPEED.  Multiplied by 10 to be nice
 * to platforms without floating point.
 */
@@ -486,6 +547,12 @@ pub const SDHCI_QUIRK_USE_WIDE8: u32 = 1 << 8;
 /*
  * EXT_CSD fields
  */
+pub const EXT_CSD_FFU_STATUS: u32 = 26; /* R/W/E_P */
+pub const EXT_CSD_MODE_OPERATION_CODES: u32 = 29; /* W/E_P */
+pub const EXT_CSD_MODE_CONFIG: u32 = 30; /* R/W/E_P */
+pub const EXT_CSD_EXCEPTION_EVENTS_STATUS: u32 = 54; /* RO, 2 bytes */
+pub const EXT_CSD_EXCEPTION_EVENTS_CTRL: u32 = 56; /* R/W, 2 bytes */
+pub const EXT_CSD_DATA_SECTOR_SIZE: u32 = 61; /* R */
 pub const EXT_CSD_ENH_START_ADDR: u32 = 136; /* R/W */
 pub const EXT_CSD_ENH_SIZE_MULT: u32 = 140; /* R/W */
 pub const EXT_CSD_GP_SIZE_MULT: u32 = 143; /* R/W */
@@ -493,11 +560,14 @@ pub const EXT_CSD_PARTITION_SETTING: u32 = 155; /* R/W */
 pub const EXT_CSD_PARTITIONS_ATTRIBUTE: u32 = 156; /* R/W */
 pub const EXT_CSD_MAX_ENH_SIZE_MULT: u32 = 157; /* R */
 pub const EXT_CSD_PARTITIONING_SUPPORT: u32 = 160; /* RO */
+pub const EXT_CSD_HPI_MGMT: u32 = 161; /* R/W */
 pub const EXT_CSD_RST_N_FUNCTION: u32 = 162; /* R/W */
 pub const EXT_CSD_BKOPS_EN: u32 = 163; /* R/W & R/W/E */
+pub const EXT_CSD_BKOPS_START: u32 = 164; /* W */
 pub const EXT_CSD_WR_REL_PARAM: u32 = 166; /* R */
 pub const EXT_CSD_WR_REL_SET: u32 = 167; /* R/W */
 pub const EXT_CSD_RPMB_MULT: u32 = 168; /* RO */
+pub const EXT_CSD_FW_CONFIG: u32 = 169; /* R/W */
 pub const EXT_CSD_ERASE_GROUP_DEF: u32 = 175; /* R/W */
 pub const EXT_CSD_BOOT_BUS_WIDTH: u32 = 177;
 pub const EXT_CSD_PART_CONF: u32 = 179; /* R/W */
@@ -507,15 +577,82 @@ pub const EXT_CSD_HS_TIMING: u32 = 185; /* R/W */
 pub const EXT_CSD_REV: u32 = 192; /* RO */
 pub const EXT_CSD_CARD_TYPE: u32 = 196; /* RO */
 pub const EXT_CSD_DRIVER_STRENGTH: u32 = 197; /* RO */
+pub const EXT_CSD_OUT_OF_INTERRUPT_TIME: u32 = 198; /* RO */
+pub const EXT_CSD_PART_SWITCH_TIME: u32 = 199; /* RO */
 pub const EXT_CSD_SEC_CNT: u32 = 212; /* RO, 4 bytes */
 pub const EXT_CSD_HC_WP_GRP_SIZE: u32 = 221; /* RO */
+pub const EXT_CSD_ERASE_TIMEOUT_MULT: u32 = 223; /* RO */
 pub const EXT_CSD_HC_ERASE_GRP_SIZE: u32 = 224; /* RO */
 pub const EXT_CSD_BOOT_MULT: u32 = 226; /* RO */
 pub const EXT_CSD_SEC_FEATURE_SUPPORT: u32 = 231; /* RO */
+pub const EXT_CSD_TRIM_MULT: u32 = 232; /* RO */
+pub const EXT_CSD_BKOPS_STATUS: u32 = 246; /* RO */
+pub const EXT_CSD_GENERIC_CMD6_TIME: u32 = 248; /* RO */
+pub const EXT_CSD_FIRMWARE_VERSION: u32 = 254; /* RO, 8 bytes */
+pub const EXT_CSD_OPTIMAL_TRIM_UNIT_SIZE: u32 = 264; /* RO */
+pub const EXT_CSD_OPTIMAL_WRITE_SIZE: u32 = 265; /* RO */
+pub const EXT_CSD_OPTIMAL_READ_SIZE: u32 = 266; /* RO */
+pub const EXT_CSD_PRE_EOL_INFO: u32 = 267; /* RO */
+pub const EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_A: u32 = 268; /* RO */
+pub const EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_B: u32 = 269; /* RO */
+pub const EXT_CSD_VENDOR_HEALTH_REPORT: u32 = 270; /* RO, 32 bytes */
+pub const EXT_CSD_NUM_OF_FW_SEC_PROG: u32 = 302; /* RO, 4 bytes */
+pub const EXT_CSD_FFU_ARG: u32 = 487; /* RO, 4 bytes */
+pub const EXT_CSD_OPERATION_CODE_TIMEOUT: u32 = 491; /* RO */
+pub const EXT_CSD_FFU_FEATURES: u32 = 492; /* RO */
+pub const EXT_CSD_SUPPORTED_MODES: u32 = 493; /* RO */
 pub const EXT_CSD_BKOPS_SUPPORT: u32 = 502; /* RO */
+pub const EXT_CSD_HPI_FEATURES: u32 = 503; /* RO */
 
 pub const EXT_CSD_PARTITION_SETTING_COMPLETED: u32 = 1 << 0;
 
+pub const EXT_CSD_VENDOR_HEALTH_REPORT_LEN: usize = 32;
+
+pub const EXT_CSD_PRE_EOL_NORMAL: u8 = 0x01; /* Normal */
+pub const EXT_CSD_PRE_EOL_WARNING: u8 = 0x02; /* 80% of reserved blocks consumed */
+pub const EXT_CSD_PRE_EOL_URGENT: u8 = 0x03; /* 90% of reserved blocks consumed */
+
+pub const EXT_CSD_LIFE_TIME_EST_EXCEEDED: u8 = 0x0B; /* Exceeded its maximum estimated life time */
+
+pub const EXT_CSD_FIRMWARE_VERSION_LEN: usize = 8;
+
+pub const EXT_CSD_BKOPS_SUPPORTED: u8 = 1 << 0; /* Device supports background operations */
+pub const EXT_CSD_MANUAL_BKOPS_EN: u8 = 1 << 0; /* Host may start BKOPS through BKOPS_START */
+pub const EXT_CSD_AUTO_BKOPS_EN: u8 = 1 << 1; /* Device may run BKOPS on its own when idle */
+
+pub const EXT_CSD_BKOPS_LEVEL_NONE: u8 = 0x00; /* No operations required */
+pub const EXT_CSD_BKOPS_LEVEL_NON_CRITICAL: u8 = 0x01; /* Operations outstanding, not critical */
+pub const EXT_CSD_BKOPS_LEVEL_IMPACTED: u8 = 0x02; /* Operations outstanding, performance impacted */
+pub const EXT_CSD_BKOPS_LEVEL_CRITICAL: u8 = 0x03; /* Operations outstanding, critical */
+
+pub const EXT_CSD_URGENT_BKOPS: u8 = 1 << 0; /* EXCEPTION_EVENTS bit for urgent BKOPS */
+
+pub const EXT_CSD_HPI_SUPPORT: u8 = 1 << 0; /* Device supports HPI */
+pub const EXT_CSD_HPI_IMPLEMENTATION: u8 = 1 << 1; /* HPI is issued with CMD12 rather than CMD13 */
+
+pub const EXT_CSD_RST_N_EN_MASK: u8 = 0x03;
+pub const EXT_CSD_RST_N_TEMP_DISABLED: u8 = 0x00; /* RST_n ignored, may still be enabled */
+pub const EXT_CSD_RST_N_ENABLED: u8 = 0x01; /* RST_n permanently enabled */
+pub const EXT_CSD_RST_N_PERM_DISABLED: u8 = 0x02; /* RST_n permanently disabled */
+
+pub const EXT_CSD_SUPPORTED_MODE_FFU: u8 = 1 << 0; /* FFU is supported */
+pub const EXT_CSD_SUPPORTED_MODE_VSM: u8 = 1 << 1; /* Vendor specific mode is supported */
+pub const EXT_CSD_FFU_FEATURE_MODE_OP_CODES: u8 = 1 << 0; /* MODE_OPERATION_CODES is supported */
+pub const EXT_CSD_FW_CONFIG_UPDATE_DISABLE: u8 = 1 << 0; /* FFU is permanently disabled */
+pub const EXT_CSD_DATA_SECTOR_SIZE_4K: u8 = 1 << 0; /* Native sector size is 4KB */
+
+pub const EXT_CSD_MODE_CONFIG_NORMAL: u8 = 0x00;
+pub const EXT_CSD_MODE_CONFIG_FFU: u8 = 0x01;
+pub const EXT_CSD_MODE_CONFIG_VENDOR: u8 = 0x10;
+
+pub const EXT_CSD_MODE_OP_FFU_INSTALL: u8 = 0x01;
+pub const EXT_CSD_MODE_OP_FFU_ABORT: u8 = 0x02;
+
+pub const EXT_CSD_FFU_STATUS_SUCCESS: u8 = 0x00;
+pub const EXT_CSD_FFU_STATUS_GENERAL_ERROR: u8 = 0x10;
+pub const EXT_CSD_FFU_STATUS_INSTALL_ERROR: u8 = 0x11;
+pub const EXT_CSD_FFU_STATUS_DOWNLOAD_ERROR: u8 = 0x12;
+
 pub const EXT_CSD_SEC_ER_EN: u32 = 1 << 0;
 pub const EXT_CSD_SEC_BD_BLK_EN: u32 = 1 << 2;
 pub const EXT_CSD_SEC_GB_CL_EN: u32 = 1 << 4;
@@ -0,0 +1,81 @@
+use core::time::Duration;
+
+use log::{debug, trace};
+
+use super::{EMmcHost, cmd::EMmcCommand, constant::*};
+use crate::err::SdError;
+
+/// Erase timeout per command for cards that do not report ERASE_TIMEOUT_MULT
+const MMC_ERASE_DEFAULT_TIMEOUT_MS: u32 = 3000;
+
+impl EMmcHost {
+    /// Discard `blocks` blocks starting at `block_id`.
+    ///
+    /// Uses TRIM when the card supports it. Otherwise only the erase groups
+    /// fully covered by the range are erased, the rest is left untouched.
+    pub fn discard_blocks(&self, block_id: u64, blocks: u64) -> Result<(), SdError> {
+        self.mmc_check_range(block_id, blocks)?;
+
+        self.mmc_check_writable()?;
+
+        if blocks == 0 {
+            return Ok(());
+        }
+
+        // Host I/O takes priority over manual background operations
+        self.mmc_bkops_preempt()?;
+
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+
+        let group = card.erase_grp_size.max(1) as u64;
+
+        if card.can_trim {
+            let end = block_id + blocks - 1;
+            // The trim timeout applies per erase group touched
+            let groups = (end / group - block_id / group + 1) as u32;
+            let timeout = card.trim_timeout.saturating_mul(groups);
+            return self.mmc_erase(block_id, end, MMC_TRIM_ARG, timeout);
+        }
+
+        let start = block_id.div_ceil(group) * group;
+        let end = (block_id + blocks) / group * group;
+        if start >= end {
+            trace!(
+                "Discard of {} blocks at {:#x} skipped, smaller than an erase group",
+                blocks, block_id
+            );
+            return Ok(());
+        }
+
+        let groups = ((end - start) / group) as u32;
+        let timeout = card.erase_timeout.saturating_mul(groups);
+        self.mmc_erase(start, end - 1, MMC_ERASE_ARG, timeout)
+    }
+
+    /// Issue CMD35/CMD36/CMD38 for blocks `start..=end` and wait for completion
+    fn mmc_erase(&self, start: u64, end: u64, arg: u32, timeout_ms: u32) -> Result<(), SdError> {
+        debug!(
+            "Erasing blocks {:#x}..={:#x} with arg {:#x}",
+            start, end, arg
+        );
+
+        let cmd = EMmcCommand::new(
+            MMC_EARSE_GROUP_START,
+            self.mmc_card_addr(start)?,
+            MMC_RSP_R1,
+        );
+        self.send_command(&cmd, None)?;
+
+        let cmd = EMmcCommand::new(MMC_EARSE_GROUP_END, self.mmc_card_addr(end)?, MMC_RSP_R1);
+        self.send_command(&cmd, None)?;
+
+        let cmd = EMmcCommand::new(MMC_ERASE, arg, MMC_RSP_R1B);
+        self.send_command(&cmd, None)?;
+
+        let timeout_ms = match timeout_ms {
+            0 => MMC_ERASE_DEFAULT_TIMEOUT_MS,
+            t => t,
+        };
+        self.mmc_poll_for_busy_timeout(true, Duration::from_millis(timeout_ms as u64), None)
+    }
+}
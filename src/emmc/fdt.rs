@@ -0,0 +1,77 @@
+use fdt_parser::Node;
+use log::{debug, warn};
+
+use super::{EMmcHost, config::EMmcChipConfig};
+use crate::err::SdError;
+
+impl EMmcChipConfig {
+    /// Read the generic mmc bindings and the Rockchip tap settings of `node`.
+    ///
+    /// Tap properties that are absent keep the RK3568 defaults. Board
+    /// properties follow the binding defaults (1-bit bus, no optional caps).
+    pub fn from_fdt(node: &Node<'_>) -> Result<Self, SdError> {
+        let mut config = Self::rk3568_config();
+
+        let flag = |name: &str| node.find_property(name).is_some();
+        let u32_prop = |name: &str| node.find_property(name).map(|prop| prop.u32());
+
+        config.bus_width = match u32_prop("bus-width").unwrap_or(1) {
+            width @ (1 | 4 | 8) => width as u8,
+            width => {
+                warn!("{}: invalid bus-width {}", node.name, width);
+                return Err(SdError::InvalidArgument);
+            }
+        };
+        config.max_frequency = u32_prop("max-frequency").unwrap_or(0);
+        config.non_removable = flag("non-removable");
+        config.broken_cd = flag("broken-cd");
+        config.no_sd = flag("no-sd");
+        config.no_sdio = flag("no-sdio");
+        config.cap_mmc_highspeed = flag("cap-mmc-highspeed");
+        config.mmc_ddr_1_8v = flag("mmc-ddr-1_8v");
+        config.mmc_hs200_1_8v = flag("mmc-hs200-1_8v");
+        config.mmc_hs400_1_8v = flag("mmc-hs400-1_8v");
+        config.mmc_hs400_enhanced_strobe = flag("mmc-hs400-enhanced-strobe");
+
+        let taps = [
+            ("rockchip,txclk-tapnum", &mut config.hs200_tx_tap),
+            ("rockchip,hs400-txclk-tapnum", &mut config.hs400_tx_tap),
+            ("rockchip,hs400-cmd-tapnum", &mut config.hs400_cmd_tap),
+            ("rockchip,hs400-strbin-tapnum", &mut config.hs400_strbin_tap),
+            (
+                "rockchip,ddr50-strbin-delay-num",
+                &mut config._ddr50_strbin_delay_num,
+            ),
+        ];
+        for (name, tap) in taps {
+            if let Some(value) = u32_prop(name) {
+                *tap = u8::try_from(value).map_err(|_| SdError::InvalidArgument)?;
+            }
+        }
+
+        debug!("{}: {:?}", node.name, config);
+        Ok(config)
+    }
+}
+
+impl EMmcHost {
+    /// Create a host from its device-tree node.
+    ///
+    /// `iomap` maps the physical register window `(address, size)` from the
+    /// first `reg` entry and returns its virtual address.
+    pub fn from_fdt(
+        node: &Node<'_>,
+        iomap: impl FnOnce(u64, usize) -> usize,
+    ) -> Result<Self, SdError> {
+        let reg = node
+            .reg()
+            .and_then(|mut reg| reg.next())
+            .ok_or(SdError::InvalidArgument)?;
+        let size = reg.size.ok_or(SdError::InvalidArgument)?;
+
+        let config = EMmcChipConfig::from_fdt(node)?;
+        let base_addr = iomap(reg.address, size);
+
+        Ok(Self::with_config(base_addr, config))
+    }
+}
@@ -0,0 +1,221 @@
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+use dma_api::{DVec, Direction};
+use log::{info, warn};
+
+use super::{
+    EMmcHost, TransferMode, aux::MMC_VERSION_5_0, block::DataBuffer, cmd::EMmcCommand, constant::*,
+};
+use crate::err::SdError;
+
+/// Blocks written per CMD23 + CMD25 pair while downloading firmware
+const FFU_CHUNK_BLOCKS: usize = 64;
+
+/// Result of a completed field firmware update
+#[derive(Debug, Clone)]
+pub struct FfuReport {
+    /// FIRMWARE_VERSION before the update
+    pub previous_version: [u8; EXT_CSD_FIRMWARE_VERSION_LEN],
+    /// FIRMWARE_VERSION reported by the re-initialized device
+    pub new_version: [u8; EXT_CSD_FIRMWARE_VERSION_LEN],
+    /// FFU_STATUS after the update
+    pub status: u8,
+}
+
+impl FfuReport {
+    /// Whether the device now reports a different firmware version
+    pub fn updated(&self) -> bool {
+        self.previous_version != self.new_version
+    }
+}
+
+fn firmware_version(ext_csd: &[u8]) -> [u8; EXT_CSD_FIRMWARE_VERSION_LEN] {
+    let mut version = [0; EXT_CSD_FIRMWARE_VERSION_LEN];
+    let start = EXT_CSD_FIRMWARE_VERSION as usize;
+    version.copy_from_slice(&ext_csd[start..start + EXT_CSD_FIRMWARE_VERSION_LEN]);
+    version
+}
+
+fn ext_csd_u32(ext_csd: &[u8], offset: u32) -> u32 {
+    let offset = offset as usize;
+    (ext_csd[offset] as u32)
+        | (ext_csd[offset + 1] as u32) << 8
+        | (ext_csd[offset + 2] as u32) << 16
+        | (ext_csd[offset + 3] as u32) << 24
+}
+
+fn ffu_status_desc(status: u8) -> &'static str {
+    match status {
+        EXT_CSD_FFU_STATUS_GENERAL_ERROR => "FFU general error",
+        EXT_CSD_FFU_STATUS_INSTALL_ERROR => "FFU firmware install error",
+        EXT_CSD_FFU_STATUS_DOWNLOAD_ERROR => "FFU firmware download error",
+        _ => "FFU unknown error",
+    }
+}
+
+impl EMmcHost {
+    /// Download and install a new device firmware through Field Firmware Update.
+    ///
+    /// The image is written to FFU_ARG in FFU mode, installed, and the card is
+    /// then re-initialized so the new firmware is running when this returns.
+    /// `firmware` must be a multiple of the device's native sector size.
+    pub fn firmware_update(&mut self, firmware: &[u8]) -> Result<FfuReport, SdError> {
+        self.mmc_reinit_if_needed()?;
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+
+        if !card.initialized.load(Ordering::SeqCst) || card.version < MMC_VERSION_5_0 {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        let ext_csd = self.mmc_read_ext_csd()?;
+
+        if ext_csd[EXT_CSD_SUPPORTED_MODES as usize] & EXT_CSD_SUPPORTED_MODE_FFU == 0 {
+            info!("FFU is not supported by the device");
+            return Err(SdError::UnsupportedCard);
+        }
+
+        if ext_csd[EXT_CSD_FW_CONFIG as usize] & EXT_CSD_FW_CONFIG_UPDATE_DISABLE != 0 {
+            info!("FFU is disabled in FW_CONFIG");
+            return Err(SdError::UnsupportedCard);
+        }
+
+        let sector_size =
+            if ext_csd[EXT_CSD_DATA_SECTOR_SIZE as usize] & EXT_CSD_DATA_SECTOR_SIZE_4K != 0 {
+                4096
+            } else {
+                MMC_MAX_BLOCK_LEN as usize
+            };
+
+        if firmware.is_empty() || firmware.len() % sector_size != 0 {
+            return Err(SdError::InvalidArgument);
+        }
+
+        let previous_version = firmware_version(&ext_csd);
+        let ffu_arg = ext_csd_u32(&ext_csd, EXT_CSD_FFU_ARG);
+        let op_codes =
+            ext_csd[EXT_CSD_FFU_FEATURES as usize] & EXT_CSD_FFU_FEATURE_MODE_OP_CODES != 0;
+
+        info!(
+            "FFU: {} bytes, FFU_ARG {:#x}, sector size {}, op codes {}",
+            firmware.len(),
+            ffu_arg,
+            sector_size,
+            op_codes
+        );
+
+        // Enter FFU mode
+        self.mmc_switch(
+            EXT_CSD_CMD_SET_NORMAL,
+            EXT_CSD_MODE_CONFIG,
+            EXT_CSD_MODE_CONFIG_FFU,
+            true,
+        )?;
+
+        if let Err(e) = self.ffu_download(ffu_arg, firmware, sector_size) {
+            warn!("FFU download failed: {:?}", e);
+            let _ = self.mmc_switch(
+                EXT_CSD_CMD_SET_NORMAL,
+                EXT_CSD_MODE_CONFIG,
+                EXT_CSD_MODE_CONFIG_NORMAL,
+                true,
+            );
+            return Err(e);
+        }
+
+        if op_codes {
+            // The device installs the firmware and returns to normal mode by itself
+            self.mmc_switch(
+                EXT_CSD_CMD_SET_NORMAL,
+                EXT_CSD_MODE_OPERATION_CODES,
+                EXT_CSD_MODE_OP_FFU_INSTALL,
+                true,
+            )?;
+        } else {
+            // Leave FFU mode, the firmware is installed on the next reset
+            self.mmc_switch(
+                EXT_CSD_CMD_SET_NORMAL,
+                EXT_CSD_MODE_CONFIG,
+                EXT_CSD_MODE_CONFIG_NORMAL,
+                true,
+            )?;
+        }
+
+        // Reset the card through the regular initialization sequence
+        self.init()?;
+
+        let ext_csd = self.mmc_read_ext_csd()?;
+        let status = ext_csd[EXT_CSD_FFU_STATUS as usize];
+        let report = FfuReport {
+            previous_version,
+            new_version: firmware_version(&ext_csd),
+            status,
+        };
+
+        if status != EXT_CSD_FFU_STATUS_SUCCESS {
+            return Err(SdError::FfuError(status, ffu_status_desc(status)));
+        }
+
+        if !report.updated() {
+            warn!("FFU completed but FIRMWARE_VERSION is unchanged");
+        }
+
+        info!(
+            "FFU: firmware version {:02x?} -> {:02x?}",
+            report.previous_version, report.new_version
+        );
+
+        Ok(report)
+    }
+
+    /// Write the image to FFU_ARG in chunks and check the device saw every sector
+    fn ffu_download(
+        &mut self,
+        ffu_arg: u32,
+        firmware: &[u8],
+        sector_size: usize,
+    ) -> Result<(), SdError> {
+        let block_len = MMC_MAX_BLOCK_LEN as usize;
+        // Each chunk must stay a multiple of the native sector size
+        let chunk_len = (FFU_CHUNK_BLOCKS * block_len) / sector_size * sector_size;
+
+        for chunk in firmware.chunks(chunk_len) {
+            let blocks = (chunk.len() / block_len) as u16;
+
+            // CMD23: pre-define the block count so no CMD12 is needed
+            let cmd = EMmcCommand::new(MMC_SET_BLOCK_COUNT, blocks as u32, MMC_RSP_R1);
+            self.send_command(&cmd, None)?;
+
+            // CMD25: FFU data always goes to FFU_ARG
+            let cmd = EMmcCommand::new(MMC_WRITE_MULTIPLE_BLOCK, ffu_arg, MMC_RSP_R1).with_data(
+                block_len as u16,
+                blocks,
+                false,
+            );
+
+            if self.transfer_mode == TransferMode::Dma {
+                let mut buffer: DVec<u8> = DVec::zeros(chunk.len(), 0x1000, Direction::ToDevice)
+                    .ok_or(SdError::MemoryError)?;
+                buffer.copy_from_slice(chunk);
+                self.send_command(&cmd, Some(DataBuffer::DmaWrite(&buffer)))?;
+            } else {
+                self.send_command(&cmd, Some(DataBuffer::Write(chunk)))?;
+            }
+
+            self.mmc_poll_for_busy(true)?;
+        }
+
+        let ext_csd: Vec<u8> = self.mmc_read_ext_csd()?;
+        let programmed = ext_csd_u32(&ext_csd, EXT_CSD_NUM_OF_FW_SEC_PROG) as usize;
+        if programmed != firmware.len() / sector_size {
+            warn!(
+                "FFU: device programmed {} of {} sectors",
+                programmed,
+                firmware.len() / sector_size
+            );
+            return Err(SdError::TransferError);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+use super::{EMmcHost, aux::MMC_VERSION_5_0, constant::*};
+use crate::err::SdError;
+
+/// Overall wear status reported through PRE_EOL_INFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthStatus {
+    /// The device does not report a value
+    Undefined,
+    Normal,
+    /// 80% of the reserved blocks are consumed
+    Warning,
+    /// 90% of the reserved blocks are consumed
+    Urgent,
+}
+
+impl From<u8> for HealthStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            EXT_CSD_PRE_EOL_NORMAL => HealthStatus::Normal,
+            EXT_CSD_PRE_EOL_WARNING => HealthStatus::Warning,
+            EXT_CSD_PRE_EOL_URGENT => HealthStatus::Urgent,
+            _ => HealthStatus::Undefined,
+        }
+    }
+}
+
+/// DEVICE_LIFE_TIME_EST_TYP_A/B value, in steps of 10% of the estimated life time used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LifeTimeEstimate(pub u8);
+
+impl LifeTimeEstimate {
+    /// Range of life time used in percent, `None` when the value is undefined
+    pub fn used_percent(&self) -> Option<(u8, u8)> {
+        match self.0 {
+            0x01..=0x0A => Some(((self.0 - 1) * 10, self.0 * 10)),
+            EXT_CSD_LIFE_TIME_EST_EXCEEDED => Some((100, 100)),
+            _ => None,
+        }
+    }
+
+    /// The device exceeded its maximum estimated life time
+    pub fn exceeded(&self) -> bool {
+        self.0 == EXT_CSD_LIFE_TIME_EST_EXCEEDED
+    }
+
+    /// Map the estimate to a wear status: 80% used is a warning, 90% is urgent
+    pub fn status(&self) -> HealthStatus {
+        match self.0 {
+            0x01..=0x08 => HealthStatus::Normal,
+            0x09 => HealthStatus::Warning,
+            0x0A | EXT_CSD_LIFE_TIME_EST_EXCEEDED => HealthStatus::Urgent,
+            _ => HealthStatus::Undefined,
+        }
+    }
+}
+
+/// Device health snapshot decoded from EXT_CSD (eMMC 5.0+)
+#[derive(Debug, Clone)]
+pub struct DeviceHealth {
+    pub pre_eol_info: HealthStatus,
+    /// Life time estimate for SLC (type A) memory
+    pub life_time_est_a: LifeTimeEstimate,
+    /// Life time estimate for MLC (type B) memory
+    pub life_time_est_b: LifeTimeEstimate,
+    /// Optimal read size in bytes, 0 when not defined
+    pub optimal_read_size: u32,
+    /// Optimal write size in bytes, 0 when not defined
+    pub optimal_write_size: u32,
+    /// Optimal trim unit size in bytes, 0 when not defined
+    pub optimal_trim_unit_size: u64,
+    /// Vendor proprietary health report, `None` when the device leaves it blank
+    pub vendor_health_report: Option<Vec<u8>>,
+}
+
+impl DeviceHealth {
+    /// Decode the health fields from a raw EXT_CSD
+    pub fn from_ext_csd(ext_csd: &[u8]) -> Self {
+        let optimal_trim = ext_csd[EXT_CSD_OPTIMAL_TRIM_UNIT_SIZE as usize];
+        let vendor_start = EXT_CSD_VENDOR_HEALTH_REPORT as usize;
+        let vendor = &ext_csd[vendor_start..vendor_start + EXT_CSD_VENDOR_HEALTH_REPORT_LEN];
+
+        Self {
+            pre_eol_info: HealthStatus::from(ext_csd[EXT_CSD_PRE_EOL_INFO as usize]),
+            life_time_est_a: LifeTimeEstimate(ext_csd[EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_A as usize]),
+            life_time_est_b: LifeTimeEstimate(ext_csd[EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_B as usize]),
+            optimal_read_size: ext_csd[EXT_CSD_OPTIMAL_READ_SIZE as usize] as u32 * 4096,
+            optimal_write_size: ext_csd[EXT_CSD_OPTIMAL_WRITE_SIZE as usize] as u32 * 4096,
+            optimal_trim_unit_size: if optimal_trim == 0 {
+                0
+            } else {
+                4096u64 << (optimal_trim - 1).min(31)
+            },
+            vendor_health_report: if vendor.iter().any(|&b| b != 0) {
+                Some(vendor.to_vec())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Worst status across PRE_EOL_INFO and both life time estimates
+    pub fn status(&self) -> HealthStatus {
+        self.pre_eol_info
+            .max(self.life_time_est_a.status())
+            .max(self.life_time_est_b.status())
+    }
+}
+
+impl EMmcHost {
+    /// Read the device health report from EXT_CSD.
+    ///
+    /// The fields were introduced with eMMC 5.0 (EXT_CSD_REV 7), older devices
+    /// return `SdError::UnsupportedCard`.
+    pub fn health(&mut self) -> Result<DeviceHealth, SdError> {
+        self.mmc_reinit_if_needed()?;
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+
+        if !card.initialized.load(Ordering::SeqCst) {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        if card.version < MMC_VERSION_5_0 {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        // Life time estimates change over time, always fetch a fresh copy
+        let ext_csd = self.mmc_read_ext_csd()?;
+
+        Ok(DeviceHealth::from_ext_csd(&ext_csd))
+    }
+}
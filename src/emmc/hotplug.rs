@@ -0,0 +1,173 @@
+use alloc::boxed::Box;
+use core::time::Duration;
+
+use log::{info, warn};
+
+use super::{EMmcHost, constant::*};
+use crate::err::SdError;
+
+/// Time the card detect level must stay unchanged before it is trusted
+pub const EMMC_CD_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Board GPIO card detect, used instead of the controller CD pin when present
+pub trait CardDetect: Send {
+    /// A card sits in the slot, polarity already applied
+    fn is_present(&self) -> bool;
+}
+
+impl core::fmt::Debug for dyn CardDetect {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "CardDetect")
+    }
+}
+
+/// Notified by `poll_card_detect` about card changes
+pub trait HotplugHandler: Send {
+    /// A card was inserted and initialized successfully
+    fn card_inserted(&self, host: &EMmcHost);
+    /// The card was removed, its state is already torn down
+    fn card_removed(&self) {}
+}
+
+impl core::fmt::Debug for dyn HotplugHandler {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "HotplugHandler")
+    }
+}
+
+/// Card change reported by `poll_card_detect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardEvent {
+    Inserted,
+    Removed,
+}
+
+/// Debounced card detect state
+#[derive(Debug, Default)]
+pub(crate) struct CdState {
+    /// Presence the host acted on last
+    present: bool,
+    /// Last raw level seen and when it was first seen
+    level: bool,
+    since: Duration,
+}
+
+impl EMmcHost {
+    /// Detect the card through `cd` instead of the controller CD pin
+    pub fn with_card_detect(mut self, cd: Box<dyn CardDetect>) -> Self {
+        self.card_detect = Some(cd);
+        self
+    }
+
+    /// Call `handler` when `poll_card_detect` sees the card come or go
+    pub fn with_hotplug_handler(mut self, handler: Box<dyn HotplugHandler>) -> Self {
+        self.hotplug = Some(handler);
+        self
+    }
+
+    /// Require the card detect level to be stable for `debounce`
+    pub fn with_cd_debounce(mut self, debounce: Duration) -> Self {
+        self.cd_debounce = debounce;
+        self
+    }
+
+    /// The slot can lose its card, card detect is meaningful
+    pub fn is_removable(&self) -> bool {
+        !self.config.non_removable && !self.config.broken_cd
+    }
+
+    /// Raw card detect level, true for non-removable slots
+    pub fn is_card_present(&self) -> bool {
+        if !self.is_removable() {
+            return true;
+        }
+
+        match &self.card_detect {
+            Some(cd) => cd.is_present(),
+            None => {
+                let state = self.read_reg(EMMC_PRESENT_STATE);
+                (state & EMMC_CARD_INSERTED) != 0 && (state & EMMC_CARD_STABLE) != 0
+            }
+        }
+    }
+
+    /// Check for card insertion or removal and act on it.
+    ///
+    /// Call periodically or from the controller interrupt handler. A removed
+    /// card is torn down, requests fail with `SdError::NoCard` from then on.
+    /// An inserted card is initialized before the handler is notified; if
+    /// that fails the error is returned and the card stays unusable until it
+    /// is inserted again.
+    pub fn poll_card_detect(&mut self) -> Result<Option<CardEvent>, SdError> {
+        if !self.is_removable() {
+            return Ok(None);
+        }
+
+        // CD interrupts latch changes too short to be seen by sampling
+        let status = self.read_reg16(EMMC_NORMAL_INT_STAT) & EMMC_INT_CARD_DETECT as u16;
+        if status != 0 {
+            self.write_reg16(EMMC_NORMAL_INT_STAT, status);
+        }
+
+        let now = self.kernel()?.now();
+        let level = self.is_card_present();
+        if level != self.cd_state.level {
+            self.cd_state.level = level;
+            self.cd_state.since = now;
+        }
+
+        let removed = status & EMMC_INT_CARD_REMOVE as u16 != 0;
+        if self.cd_state.present && (removed || !level) {
+            // Removal is acted on at once, the card is gone either way
+            self.mmc_remove_card();
+            return Ok(Some(CardEvent::Removed));
+        }
+
+        if level && !self.cd_state.present && now - self.cd_state.since >= self.cd_debounce {
+            info!("Card inserted");
+            self.cd_state.present = true;
+            self.init()?;
+
+            if let Some(handler) = &self.hotplug {
+                handler.card_inserted(self);
+            }
+            return Ok(Some(CardEvent::Inserted));
+        }
+
+        Ok(None)
+    }
+
+    /// Record the presence seen by `init`
+    pub(crate) fn mmc_cd_init(&mut self) -> Result<bool, SdError> {
+        let present = self.is_card_present();
+        self.cd_state = CdState {
+            present,
+            level: present,
+            since: self.kernel()?.now(),
+        };
+        Ok(present)
+    }
+
+    /// CD interrupts to enable, none when detection goes through a GPIO
+    pub(crate) fn mmc_cd_int_mask(&self) -> u32 {
+        if self.is_removable() && self.card_detect.is_none() {
+            EMMC_INT_CARD_DETECT
+        } else {
+            0
+        }
+    }
+
+    fn mmc_remove_card(&mut self) {
+        info!("Card removed");
+        self.cd_state.present = false;
+        self.card = None;
+
+        if let Err(e) = self.power_off() {
+            warn!("Failed to power off the slot: {:?}", e);
+        }
+
+        if let Some(handler) = &self.hotplug {
+            handler.card_removed();
+        }
+    }
+}
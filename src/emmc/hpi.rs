@@ -0,0 +1,93 @@
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+use log::{debug, warn};
+
+use super::{EMmcHost, cmd::EMmcCommand, constant::*};
+use crate::err::SdError;
+
+/// Busy timeout after HPI when the device does not report OUT_OF_INTERRUPT_TIME
+const HPI_DEFAULT_TIMEOUT_MS: u32 = 100;
+
+impl EMmcHost {
+    /// Detect HPI from EXT_CSD and enable it through HPI_MGMT
+    pub(crate) fn mmc_init_hpi(&mut self, ext_csd: &[u8]) -> Result<(), SdError> {
+        // HPI was introduced with eMMC 4.41 (EXT_CSD_REV 5)
+        if ext_csd[EXT_CSD_REV as usize] < 5
+            || ext_csd[EXT_CSD_HPI_FEATURES as usize] & EXT_CSD_HPI_SUPPORT == 0
+        {
+            return Ok(());
+        }
+
+        let hpi_cmd = if ext_csd[EXT_CSD_HPI_FEATURES as usize] & EXT_CSD_HPI_IMPLEMENTATION != 0 {
+            MMC_STOP_TRANSMISSION
+        } else {
+            MMC_SEND_STATUS
+        };
+        self.set_hpi_support(true).unwrap();
+        self.set_hpi_cmd(hpi_cmd).unwrap();
+        // OUT_OF_INTERRUPT_TIME is given in units of 10ms
+        self.set_out_of_int_time(ext_csd[EXT_CSD_OUT_OF_INTERRUPT_TIME as usize] as u32 * 10)
+            .unwrap();
+
+        if ext_csd[EXT_CSD_HPI_MGMT as usize] & 0x1 == 0 {
+            // Keep going without HPI if the device refuses to enable it
+            if let Err(e) = self.mmc_switch(EXT_CSD_CMD_SET_NORMAL, EXT_CSD_HPI_MGMT, 1, true) {
+                warn!("Failed to enable HPI: {:?}", e);
+                return Ok(());
+            }
+        }
+
+        self.set_hpi_en(true).unwrap();
+        debug!("HPI enabled, using CMD{}", hpi_cmd);
+
+        Ok(())
+    }
+
+    /// Interrupt a long-running erase, write or background operation with HPI.
+    ///
+    /// Returns once the card has left the busy state, bounded by the
+    /// OUT_OF_INTERRUPT_TIME reported by the device. Does nothing if the card
+    /// is not busy.
+    pub fn hpi_interrupt(&self) -> Result<(), SdError> {
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+
+        if !card.hpi_en {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        card.bkops_running.store(false, Ordering::SeqCst);
+
+        if !self.mmc_card_busy() {
+            return Ok(());
+        }
+
+        self.mmc_send_hpi()
+    }
+
+    /// Send a High Priority Interrupt to the card and wait until it leaves the busy state
+    pub(crate) fn mmc_send_hpi(&self) -> Result<(), SdError> {
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+
+        if !card.hpi_en {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        let resp_type = if card.hpi_cmd == MMC_STOP_TRANSMISSION {
+            MMC_RSP_R1B
+        } else {
+            MMC_RSP_R1
+        };
+        let cmd = EMmcCommand::new(card.hpi_cmd, (card.rca << 16) | 0x1, resp_type);
+        self.send_command(&cmd, None)?;
+
+        let timeout_ms = match card.out_of_int_time {
+            0 => HPI_DEFAULT_TIMEOUT_MS,
+            t => t,
+        };
+
+        // Busy is signalled on DAT0 after CMD12; CMD13 needs status polling
+        let timeout = Duration::from_millis(timeout_ms as u64);
+        self.mmc_poll_for_busy_timeout(card.hpi_cmd != MMC_STOP_TRANSMISSION, timeout, None)
+    }
+}
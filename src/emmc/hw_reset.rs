@@ -0,0 +1,109 @@
+use alloc::boxed::Box;
+
+use log::{info, warn};
+
+use super::{EMmcHost, constant::*};
+use crate::err::SdError;
+
+/// RST_n low time, at least 1us (tRSTW)
+const EMMC_RST_N_PULSE_US: u64 = 10;
+/// Wait after RST_n goes high before the first command, at least 200us (tRSCA)
+const EMMC_RST_N_RECOVERY_US: u64 = 300;
+
+/// Board GPIO wired to the eMMC RST_n pin, used instead of the controller
+/// output when present
+pub trait ResetGpio: Send {
+    /// Drive RST_n low (`asserted`) or high
+    fn set_reset(&self, asserted: bool) -> Result<(), SdError>;
+}
+
+impl core::fmt::Debug for dyn ResetGpio {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ResetGpio")
+    }
+}
+
+impl EMmcHost {
+    /// Pulse RST_n through `gpio` instead of the controller card reset output
+    pub fn with_reset_gpio(mut self, gpio: Box<dyn ResetGpio>) -> Self {
+        self.reset_gpio = Some(gpio);
+        self
+    }
+
+    /// The card reacts to RST_n, according to its last read EXT_CSD
+    pub fn hw_reset_enabled(&self) -> bool {
+        self.rst_n_function()
+            .is_some_and(|func| func & EXT_CSD_RST_N_EN_MASK == EXT_CSD_RST_N_ENABLED)
+    }
+
+    /// Permanently enable the RST_n input of the card.
+    ///
+    /// RST_n_FUNCTION is one-time programmable: once enabled, RST_n can
+    /// never be disabled again. The current value is read back from the
+    /// card first so a stale copy never leads to a second write.
+    pub fn enable_hw_reset(&mut self) -> Result<(), SdError> {
+        self.mmc_reinit_if_needed()?;
+        if !self.initialized().unwrap_or(false) {
+            return Err(SdError::NoCard);
+        }
+
+        let ext_csd = self.mmc_read_ext_csd()?;
+        let func = ext_csd[EXT_CSD_RST_N_FUNCTION as usize];
+        self.set_rst_n_function(func).unwrap();
+
+        match func & EXT_CSD_RST_N_EN_MASK {
+            EXT_CSD_RST_N_ENABLED => return Ok(()),
+            EXT_CSD_RST_N_TEMP_DISABLED => {}
+            _ => {
+                warn!("RST_n is permanently disabled on this card");
+                return Err(SdError::UnsupportedCard);
+            }
+        }
+
+        info!("Permanently enabling RST_n");
+        let value = (func & !EXT_CSD_RST_N_EN_MASK) | EXT_CSD_RST_N_ENABLED;
+        self.mmc_switch(EXT_CSD_CMD_SET_NORMAL, EXT_CSD_RST_N_FUNCTION, value, true)?;
+
+        let ext_csd = self.mmc_read_ext_csd()?;
+        let func = ext_csd[EXT_CSD_RST_N_FUNCTION as usize];
+        self.set_rst_n_function(func).unwrap();
+        if func & EXT_CSD_RST_N_EN_MASK != EXT_CSD_RST_N_ENABLED {
+            return Err(SdError::CommandError);
+        }
+
+        Ok(())
+    }
+
+    /// Reset the card through RST_n and initialize it again.
+    ///
+    /// This works even when the card no longer answers CMD0, provided RST_n
+    /// was enabled with `enable_hw_reset`. Cards with RST_n disabled ignore
+    /// the pulse and are only power cycled by the re-initialization.
+    pub fn hw_reset(&mut self) -> Result<(), SdError> {
+        if self.card.is_some() && !self.hw_reset_enabled() {
+            warn!("RST_n not enabled on the card, the reset pulse is ignored");
+        }
+
+        info!("Hardware resetting card");
+        self.mmc_rst_n_pulse()?;
+        self.mmc_reinit()
+    }
+
+    fn mmc_rst_n_pulse(&self) -> Result<(), SdError> {
+        if let Some(gpio) = &self.reset_gpio {
+            gpio.set_reset(true)?;
+            self.delay_us(EMMC_RST_N_PULSE_US)?;
+            gpio.set_reset(false)?;
+        } else {
+            let ctrl = self.read_reg16(EMMC_EMMC_CTRL)
+                | (DWCMSHC_CARD_IS_EMMC | DWCMSHC_EMMC_RST_N_OE) as u16;
+
+            self.write_reg16(EMMC_EMMC_CTRL, ctrl & !(DWCMSHC_EMMC_RST_N as u16));
+            self.delay_us(EMMC_RST_N_PULSE_US)?;
+            self.write_reg16(EMMC_EMMC_CTRL, ctrl | DWCMSHC_EMMC_RST_N as u16);
+        }
+
+        self.delay_us(EMMC_RST_N_RECOVERY_US)?;
+        Ok(())
+    }
+}
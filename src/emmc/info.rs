@@ -1,5 +1,6 @@
-use super::{EMmcHost, block::EMmcCard, cmd::EMmcCommand, constant::*};
+use super::{CardStatus, EMmcHost, block::EMmcCard, cmd::EMmcCommand, constant::*};
 use crate::err::SdError;
+use alloc::string::String;
 use core::sync::atomic::Ordering;
 
 // Card information structure
@@ -7,15 +8,20 @@ use core::sync::atomic::Ordering;
 pub struct CardInfo {
     pub card_type: CardType,
     pub manufacturer_id: u8,
-    pub application_id: u16,
+    pub manufacturer_name: &'static str,
+    pub oem_id: u16,
+    pub product_name: String,
+    pub product_revision: (u8, u8),
     pub serial_number: u32,
     pub manufacturing_month: u8,
     pub manufacturing_year: u16,
+    pub csd_structure: u8,
+    pub spec_version: u8,
     pub capacity_bytes: u64,
     pub block_size: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CardType {
     Unknown,
     Mmc,
@@ -25,9 +31,265 @@ pub enum CardType {
     MmcHc,
 }
 
+impl CardType {
+    /// Whether the card follows the SD register layouts rather than the (e)MMC ones
+    pub fn is_sd(&self) -> bool {
+        matches!(self, CardType::SdV1 | CardType::SdV2 | CardType::SdHc)
+    }
+}
+
+/// Decoded Card Identification register
+///
+/// The raw words are in the order produced by `SdResponse::as_r2`, i.e. `raw[0]`
+/// holds bits [127:96] and the CRC byte is absent.
+#[derive(Debug, Clone, Copy)]
+pub struct Cid {
+    pub manufacturer_id: u8,
+    /// Card/BGA indicator (eMMC only)
+    pub device_type: u8,
+    /// 8-bit OEM ID on eMMC, two ASCII characters on SD
+    pub oem_id: u16,
+    pub product_name: [u8; 6],
+    pub product_revision: u8,
+    pub serial_number: u32,
+    pub manufacturing_month: u8,
+    pub manufacturing_year: u16,
+}
+
+impl Cid {
+    /// Decode a CID using the layout matching `card_type`.
+    ///
+    /// `ext_csd_rev` is only used for eMMC, where cards with EXT_CSD_REV > 4
+    /// (eMMC 4.41+) count manufacturing years from 2013 instead of 1997.
+    pub fn decode(raw: &[u32; 4], card_type: CardType, ext_csd_rev: u8) -> Self {
+        if card_type.is_sd() {
+            Self::decode_sd(raw)
+        } else {
+            Self::decode_mmc(raw, ext_csd_rev)
+        }
+    }
+
+    /// eMMC CID: MID[127:120] CBX[113:112] OID[111:104] PNM[103:56]
+    /// PRV[55:48] PSN[47:16] MDT[15:8]
+    pub fn decode_mmc(raw: &[u32; 4], ext_csd_rev: u8) -> Self {
+        let product_name = [
+            raw[0] as u8,
+            (raw[1] >> 24) as u8,
+            (raw[1] >> 16) as u8,
+            (raw[1] >> 8) as u8,
+            raw[1] as u8,
+            (raw[2] >> 24) as u8,
+        ];
+
+        let mdt = (raw[3] >> 8) & 0xFF;
+        let mut manufacturing_year = (mdt & 0xF) as u16 + 1997;
+        if ext_csd_rev > 4 && manufacturing_year < 2010 {
+            manufacturing_year += 16;
+        }
+
+        Self {
+            manufacturer_id: (raw[0] >> 24) as u8,
+            device_type: ((raw[0] >> 16) & 0x3) as u8,
+            oem_id: ((raw[0] >> 8) & 0xFF) as u16,
+            product_name,
+            product_revision: ((raw[2] >> 16) & 0xFF) as u8,
+            serial_number: ((raw[2] & 0xFFFF) << 16) | (raw[3] >> 16),
+            manufacturing_month: (mdt >> 4) as u8,
+            manufacturing_year,
+        }
+    }
+
+    /// SD CID: MID[127:120] OID[119:104] PNM[103:64] PRV[63:56]
+    /// PSN[55:24] MDT[19:8]
+    pub fn decode_sd(raw: &[u32; 4]) -> Self {
+        let product_name = [
+            raw[0] as u8,
+            (raw[1] >> 24) as u8,
+            (raw[1] >> 16) as u8,
+            (raw[1] >> 8) as u8,
+            raw[1] as u8,
+            0,
+        ];
+
+        Self {
+            manufacturer_id: (raw[0] >> 24) as u8,
+            device_type: 0,
+            oem_id: ((raw[0] >> 8) & 0xFFFF) as u16,
+            product_name,
+            product_revision: (raw[2] >> 24) as u8,
+            serial_number: ((raw[2] & 0xFF_FFFF) << 8) | (raw[3] >> 24),
+            manufacturing_month: ((raw[3] >> 8) & 0xF) as u8,
+            manufacturing_year: ((raw[3] >> 12) & 0xFF) as u16 + 2000,
+        }
+    }
+
+    /// Product name with trailing padding and non-printable bytes removed
+    pub fn product_name(&self) -> String {
+        self.product_name
+            .iter()
+            .map(|&c| {
+                if c.is_ascii_graphic() || c == b' ' {
+                    c as char
+                } else {
+                    ' '
+                }
+            })
+            .collect::<String>()
+            .trim_end()
+            .into()
+    }
+
+    /// Product revision as (major, minor), stored as BCD n.m
+    pub fn product_revision(&self) -> (u8, u8) {
+        (self.product_revision >> 4, self.product_revision & 0xF)
+    }
+}
+
+/// Decoded Card-Specific Data register
+///
+/// Field positions are shared by the eMMC CSD and SD CSD version 1.0; SD
+/// CSD versions 2.0 and 3.0 only differ in the width and position of C_SIZE.
+#[derive(Debug, Clone, Copy)]
+pub struct Csd {
+    pub csd_structure: u8,
+    /// SPEC_VERS, eMMC only (reserved on SD)
+    pub spec_version: u8,
+    pub taac: u8,
+    pub nsac: u8,
+    pub tran_speed: u8,
+    pub ccc: u16,
+    pub read_bl_len: u8,
+    pub dsr_imp: bool,
+    pub c_size: u32,
+    pub c_size_mult: u8,
+    pub erase_grp_size: u8,
+    pub erase_grp_mult: u8,
+    pub wp_grp_size: u8,
+    pub r2w_factor: u8,
+    pub write_bl_len: u8,
+    pub perm_write_protect: bool,
+    pub tmp_write_protect: bool,
+    is_sd: bool,
+}
+
+impl Csd {
+    /// Decode a CSD using the layout matching `card_type`
+    pub fn decode(raw: &[u32; 4], card_type: CardType) -> Self {
+        let csd_structure = (raw[0] >> 30) as u8;
+        let is_sd = card_type.is_sd();
+
+        let c_size = match (is_sd, csd_structure) {
+            // SD CSD 2.0: C_SIZE[69:48]
+            (true, 1) => ((raw[1] & 0x3F) << 16) | (raw[2] >> 16),
+            // SD CSD 3.0 (SDUC): C_SIZE[75:48]
+            (true, 2) => ((raw[1] & 0xFFF) << 16) | (raw[2] >> 16),
+            // eMMC and SD CSD 1.0: C_SIZE[73:62]
+            _ => ((raw[1] & 0x3FF) << 2) | (raw[2] >> 30),
+        };
+
+        Self {
+            csd_structure,
+            spec_version: if is_sd {
+                0
+            } else {
+                ((raw[0] >> 26) & 0xF) as u8
+            },
+            taac: (raw[0] >> 16) as u8,
+            nsac: (raw[0] >> 8) as u8,
+            tran_speed: raw[0] as u8,
+            ccc: (raw[1] >> 20) as u16,
+            read_bl_len: ((raw[1] >> 16) & 0xF) as u8,
+            dsr_imp: (raw[1] >> 12) & 0x1 != 0,
+            c_size,
+            c_size_mult: ((raw[2] >> 15) & 0x7) as u8,
+            erase_grp_size: ((raw[2] >> 10) & 0x1F) as u8,
+            erase_grp_mult: ((raw[2] >> 5) & 0x1F) as u8,
+            wp_grp_size: (raw[2] & 0x1F) as u8,
+            r2w_factor: ((raw[3] >> 26) & 0x7) as u8,
+            write_bl_len: ((raw[3] >> 22) & 0xF) as u8,
+            perm_write_protect: (raw[3] >> 13) & 0x1 != 0,
+            tmp_write_protect: (raw[3] >> 12) & 0x1 != 0,
+            is_sd,
+        }
+    }
+
+    /// Capacity described by the CSD in bytes.
+    ///
+    /// For eMMC devices larger than 2 GiB C_SIZE reads 0xFFF and the real size
+    /// must be taken from EXT_CSD SEC_COUNT.
+    pub fn capacity_bytes(&self) -> u64 {
+        match (self.is_sd, self.csd_structure) {
+            (true, 1) | (true, 2) => (self.c_size as u64 + 1) << 19,
+            _ => {
+                (self.c_size as u64 + 1) << (self.c_size_mult as u64 + 2 + self.read_bl_len as u64)
+            }
+        }
+    }
+
+    /// Maximum legacy transfer rate in Hz decoded from TRAN_SPEED
+    pub fn max_dtr(&self) -> u32 {
+        let freq = FBASE[(self.tran_speed & 0x7) as usize] as u32;
+        let mult = MULTIPLIERS[((self.tran_speed >> 3) & 0xF) as usize] as u32;
+        freq * mult
+    }
+
+    /// Fixed part of the data access time (TAAC) in nanoseconds
+    pub fn access_time_ns(&self) -> u32 {
+        let unit = TAAC_UNIT_NS[(self.taac & 0x7) as usize];
+        let mult = MULTIPLIERS[((self.taac >> 3) & 0xF) as usize] as u32;
+        unit * mult / 10
+    }
+
+    /// Clock dependent part of the data access time (NSAC) in bus clock cycles
+    pub fn access_time_clks(&self) -> u32 {
+        self.nsac as u32 * 100
+    }
+
+    /// Erase group size in 512-byte sectors when EXT_CSD ERASE_GROUP_DEF is clear
+    pub fn erase_group_sectors(&self) -> u32 {
+        (self.erase_grp_size as u32 + 1) * (self.erase_grp_mult as u32 + 1)
+    }
+}
+
+/// Look up the manufacturer name for a CID MID.
+///
+/// eMMC MIDs are JEDEC assigned while SD MIDs are assigned by the SD
+/// association, so the same value maps to different vendors.
+pub fn manufacturer_name(manufacturer_id: u8, card_type: CardType) -> &'static str {
+    if card_type.is_sd() {
+        match manufacturer_id {
+            0x01 => "Panasonic",
+            0x02 => "Toshiba",
+            0x03 => "SanDisk",
+            0x1B => "Samsung",
+            0x1D => "ADATA",
+            0x27 => "Phison",
+            0x28 => "Lexar",
+            0x31 => "Silicon Power",
+            0x41 => "Kingston",
+            0x74 => "Transcend",
+            0x76 => "Patriot",
+            0x82 => "Sony",
+            _ => "Unknown",
+        }
+    } else {
+        match manufacturer_id {
+            0x02 | 0x45 => "SanDisk",
+            0x11 => "Toshiba",
+            0x13 | 0xFE => "Micron",
+            0x15 => "Samsung",
+            0x70 => "Kingston",
+            0x88 => "Foresee",
+            0x90 => "SK Hynix",
+            0x9B => "YMTC",
+            _ => "Unknown",
+        }
+    }
+}
+
 impl EMmcHost {
     // Get card status
-    pub fn get_status(&self) -> Result<u32, SdError> {
+    pub fn get_status(&self) -> Result<CardStatus, SdError> {
         // Check if card is initialized
         let card = match &self.card {
             Some(card) => card,
@@ -39,11 +301,23 @@ impl EMmcHost {
         }
 
         // Send SEND_STATUS command
-        let cmd = EMmcCommand::new(MMC_SEND_STATUS, card.rca, MMC_RSP_R1);
+        let cmd = EMmcCommand::new(MMC_SEND_STATUS, card.rca << 16, MMC_RSP_R1);
         self.send_command(&cmd, None)?;
         let response = self.get_response();
 
-        Ok(response.as_r1())
+        Ok(CardStatus::from(response.as_r1()))
+    }
+
+    // Get decoded CID of the attached card
+    pub fn get_cid(&self) -> Result<Cid, SdError> {
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+        Ok(Cid::decode(&card.cid, card.card_type, card.ext_csd_rev))
+    }
+
+    // Get decoded CSD of the attached card
+    pub fn get_csd(&self) -> Result<Csd, SdError> {
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+        Ok(Csd::decode(&card.csd, card.card_type))
     }
 
     // Get card info
@@ -58,25 +332,22 @@ impl EMmcHost {
             return Err(SdError::UnsupportedCard);
         }
 
-        // Extract information from CID
-        let cid = card.cid;
-
-        // SD card CID format
-        let manufacturer_id = (cid[0] >> 24) as u8;
-        let application_id = ((cid[0] >> 8) & 0xFFFF) as u16;
-        let serial_number = ((cid[0] & 0xFF) << 24) | ((cid[1] >> 8) & 0xFFFFFF);
-
-        // Extract manufacturing date
-        let manufacturing_year = (((cid[1] & 0xF) << 4) | ((cid[2] >> 28) & 0xF)) as u16 + 2000;
-        let manufacturing_month = ((cid[2] >> 24) & 0xF) as u8;
+        // Decode CID/CSD with the layout matching the card family
+        let cid = Cid::decode(&card.cid, card.card_type, card.ext_csd_rev);
+        let csd = Csd::decode(&card.csd, card.card_type);
 
         let card_info = CardInfo {
             card_type: card.card_type,
-            manufacturer_id,
-            application_id,
-            serial_number,
-            manufacturing_month,
-            manufacturing_year,
+            manufacturer_id: cid.manufacturer_id,
+            manufacturer_name: manufacturer_name(cid.manufacturer_id, card.card_type),
+            oem_id: cid.oem_id,
+            product_name: cid.product_name(),
+            product_revision: cid.product_revision(),
+            serial_number: cid.serial_number,
+            manufacturing_month: cid.manufacturing_month,
+            manufacturing_year: cid.manufacturing_year,
+            csd_structure: csd.csd_structure,
+            spec_version: csd.spec_version,
             capacity_bytes: card.capacity_blocks * 512,
             block_size: 512,
         };
@@ -189,7 +460,19 @@ impl_emmc_card_proxy!(
     ext_csd_rev: u8,
     ext_csd_sectors: u64,
     hs_max_dtr: u32,
-    raw_driver_strength: u8
+    raw_driver_strength: u8,
+    bkops_support: bool,
+    bkops_en: u8,
+    hpi_support: bool,
+    hpi_cmd: u8,
+    hpi_en: bool,
+    out_of_int_time: u32,
+    can_trim: bool,
+    erase_timeout: u32,
+    trim_timeout: u32,
+    generic_cmd6_time: u32,
+    part_switch_time: u32,
+    rst_n_function: u8
 );
 
 impl EMmcHost {
@@ -1,30 +1,62 @@
-extern crate alloc;
-
+mod bkops;
 mod block;
+mod caps;
 mod cmd;
 mod config;
+mod erase;
+mod fdt;
+mod ffu;
+mod health;
+mod hotplug;
+mod hpi;
+mod hw_reset;
 mod info;
+mod power;
+mod recovery;
 mod regs;
 mod rockchip;
+mod shared;
+mod status;
+mod time;
+mod timeout;
+mod transfer;
+mod write_protect;
 
 pub mod aux;
 pub mod clock;
 pub mod constant;
 
-use crate::{delay_us, err::*};
+use crate::{Kernel, err::*};
+use alloc::boxed::Box;
 use aux::{
     MMC_VERSION_1_2, MMC_VERSION_1_4, MMC_VERSION_2_2, MMC_VERSION_3, MMC_VERSION_4,
     MMC_VERSION_4_1, MMC_VERSION_4_2, MMC_VERSION_4_3, MMC_VERSION_4_5, MMC_VERSION_4_41,
     MMC_VERSION_5_0, MMC_VERSION_5_1, MMC_VERSION_UNKNOWN, generic_fls, lldiv,
 };
+pub use bkops::BkopsStatus;
 use block::EMmcCard;
+pub use caps::HostCapabilities;
 use cmd::*;
+pub use config::EMmcChipConfig;
 use constant::*;
 use core::fmt::Display;
-#[cfg(feature = "dma")]
-use dma_api::{DVec, Direction};
-use info::CardType;
+use core::time::Duration;
+pub use ffu::FfuReport;
+pub use health::{DeviceHealth, HealthStatus, LifeTimeEstimate};
+use hotplug::CdState;
+pub use hotplug::{CardDetect, CardEvent, EMMC_CD_DEBOUNCE, HotplugHandler};
+pub use hw_reset::ResetGpio;
+pub use info::{CardInfo, CardType, Cid, Csd, manufacturer_name};
 use log::{debug, info, trace};
+pub use power::{Regulator, SignalVoltage};
+use recovery::RecoveryState;
+pub use recovery::{EMMC_IO_RETRIES, RecoveryAction, RecoveryStats};
+pub use shared::SharedEMmcHost;
+pub use status::{CardState, CardStatus};
+pub use time::*;
+pub use timeout::TimeoutPolicy;
+pub use transfer::TransferMode;
+pub use write_protect::WriteProtect;
 
 // SD Host Controller structure
 #[derive(Debug)]
@@ -35,9 +67,31 @@ pub struct EMmcHost {
     clock_base: u32,
     voltages: u32,
     quirks: u32,
-    // clock: u32,
+    clock: u32,
+    clk: Option<Box<dyn clock::Clk>>,
+    vmmc: Option<Box<dyn Regulator>>,
+    vqmmc: Option<Box<dyn Regulator>>,
+    reset_gpio: Option<Box<dyn ResetGpio>>,
+    card_detect: Option<Box<dyn CardDetect>>,
+    hotplug: Option<Box<dyn HotplugHandler>>,
+    cd_debounce: Duration,
+    cd_state: CdState,
+    write_protect: Option<Box<dyn WriteProtect>>,
+    read_only: bool,
+    /// Bit per PARTITION_ACCESS value of partitions that must not be written
+    read_only_parts: u8,
+    signal_voltage: SignalVoltage,
+    kernel: Option<&'static dyn Kernel>,
+    /// Never sleep or yield, set while owned by `SharedEMmcHost`
+    atomic: bool,
+    timeouts: TimeoutPolicy,
+    transfer_mode: TransferMode,
+    io_retries: u32,
+    recovery: RecoveryState,
     host_caps: u32,
+    capabilities: HostCapabilities,
     version: u16,
+    config: EMmcChipConfig,
 }
 
 impl Display for EMmcHost {
@@ -52,6 +106,11 @@ impl Display for EMmcHost {
 
 impl EMmcHost {
     pub fn new(base_addr: usize) -> Self {
+        Self::with_config(base_addr, EMmcChipConfig::rk3568_config())
+    }
+
+    /// Create a host for a board described by `config`
+    pub fn with_config(base_addr: usize, config: EMmcChipConfig) -> Self {
         let mut host = Self {
             base_addr,
             card: None,
@@ -59,9 +118,29 @@ impl EMmcHost {
             clock_base: 0,
             voltages: 0,
             quirks: 0,
-            // clock: 0,
+            clock: 0,
+            clk: None,
+            vmmc: None,
+            vqmmc: None,
+            reset_gpio: None,
+            card_detect: None,
+            hotplug: None,
+            cd_debounce: EMMC_CD_DEBOUNCE,
+            cd_state: CdState::default(),
+            write_protect: None,
+            read_only: false,
+            read_only_parts: 0,
+            signal_voltage: SignalVoltage::V330,
+            kernel: None,
+            atomic: false,
+            timeouts: TimeoutPolicy::default(),
+            transfer_mode: TransferMode::default(),
+            io_retries: EMMC_IO_RETRIES,
+            recovery: RecoveryState::default(),
             host_caps: 0,
+            capabilities: HostCapabilities::default(),
             version: 0,
+            config,
         };
 
         // Read capabilities
@@ -76,6 +155,11 @@ impl EMmcHost {
         host
     }
 
+    /// Board configuration the host was created with
+    pub fn config(&self) -> &EMmcChipConfig {
+        &self.config
+    }
+
     // 获取 card 的不可变引用
     pub fn card(&self) -> Option<&EMmcCard> {
         self.card.as_ref()
@@ -89,6 +173,7 @@ impl EMmcHost {
     // Initialize the host controller
     pub fn init(&mut self) -> Result<(), SdError> {
         info!("Init EMMC Controller");
+        self.kernel()?;
 
         // Create card structure
         self.add_card(EMmcCard::init(CardType::Unknown));
@@ -96,8 +181,14 @@ impl EMmcHost {
         // Reset the controller
         self.reset(EMMC_RESET_ALL)?;
 
-        let is_card_inserted = self.is_card_present();
+        // Keep watching the slot while it is empty
+        self.write_reg(EMMC_NORMAL_INT_STAT_EN, self.mmc_cd_int_mask());
+        let is_card_inserted = self.mmc_cd_init()?;
         debug!("Card inserted: {}", is_card_inserted);
+        if !is_card_inserted {
+            self.card = None;
+            return Err(SdError::NoCard);
+        }
 
         let version = self.read_reg16(EMMC_HOST_CNTRL_VER);
         // version = 4.2
@@ -108,9 +199,10 @@ impl EMmcHost {
         info!("EMMC Capabilities 1: 0b{:b}", caps1);
 
         let mut clk_mul: u32 = 0;
+        let mut caps2: u32 = 0;
 
         if (version & EMMC_SPEC_VER_MASK) >= EMMC_SPEC_300 {
-            let caps2 = self.read_reg(EMMC_CAPABILITIES2);
+            caps2 = self.read_reg(EMMC_CAPABILITIES2);
             info!("EMMC Capabilities 2: 0b{:b}", caps2);
             clk_mul = (caps2 & EMMC_CLOCK_MUL_MASK) >> EMMC_CLOCK_MUL_SHIFT;
         }
@@ -133,24 +225,15 @@ impl EMmcHost {
             return Err(SdError::UnsupportedCard);
         }
 
-        self.host_caps = MMC_MODE_HS | MMC_MODE_HS_52MHZ | MMC_MODE_4BIT;
-
-        if (version & EMMC_SPEC_VER_MASK) >= EMMC_SPEC_300 && (caps1 & EMMC_CAN_DO_8BIT) == 0 {
-            self.host_caps &= !MMC_MODE_8BIT;
-        }
-
-        // 暂时写死
-        self.host_caps |= 0x48;
-
-        // debug!("self.host_caps {:#x}", self.host_caps);
+        self.mmc_negotiate_caps(caps1, caps2);
 
         let mut voltages = 0;
 
-        if (caps1 & EMMC_CAN_VDD_330) != 0 {
+        if self.capabilities.vdd_330 {
             voltages |= MMC_VDD_32_33 | MMC_VDD_33_34;
-        } else if (caps1 & EMMC_CAN_VDD_300) != 0 {
+        } else if self.capabilities.vdd_300 {
             voltages |= MMC_VDD_29_30 | MMC_VDD_30_31;
-        } else if (caps1 & EMMC_CAN_VDD_180) != 0 {
+        } else if self.capabilities.vdd_180 {
             voltages |= MMC_VDD_165_195;
         } else {
             info!("Unsupported voltage range");
@@ -165,29 +248,35 @@ impl EMmcHost {
             generic_fls(voltages) - 1
         );
 
+        self.mmc_init_bus()?;
+
+        // Initialize the card
+        self.init_card()?;
+        self.recovery.card_initialized();
+
+        info!("EMMC initialization completed successfully");
+        Ok(())
+    }
+
+    /// Power cycle the card and bring the bus to identification settings
+    fn mmc_init_bus(&mut self) -> Result<(), SdError> {
         // Perform full power cycle
-        self.sdhci_set_power(generic_fls(voltages) - 1)?;
+        self.power_cycle()?;
 
         // Enable interrupts
         self.write_reg(
             EMMC_NORMAL_INT_STAT_EN,
-            EMMC_INT_CMD_MASK | EMMC_INT_DATA_MASK,
+            EMMC_INT_CMD_MASK | EMMC_INT_DATA_MASK | self.mmc_cd_int_mask(),
         );
         self.write_reg(EMMC_SIGNAL_ENABLE, 0x0);
 
         // Set initial bus width to 1-bit
-        self.mmc_set_bus_width(1);
+        self.mmc_set_bus_width(1)?;
 
         // Set initial clock and wait for it to stabilize
-        self.mmc_set_clock(400000);
+        self.mmc_set_clock(400000)?;
 
-        self.mmc_set_timing(MMC_TIMING_LEGACY);
-
-        // Initialize the card
-        self.init_card()?;
-
-        info!("EMMC initialization completed successfully");
-        Ok(())
+        self.mmc_set_timing(MMC_TIMING_LEGACY)
     }
 
     // Reset the controller
@@ -196,29 +285,9 @@ impl EMmcHost {
         self.write_reg8(EMMC_SOFTWARE_RESET, mask);
 
         // Wait for reset to complete with timeout
-        let mut timeout = 20; // Increased timeout
-        while (self.read_reg8(EMMC_SOFTWARE_RESET) & mask) != 0 {
-            if timeout == 0 {
-                return Err(SdError::Timeout);
-            }
-            timeout -= 1;
-            delay_us(1000);
-        }
-
-        Ok(())
-    }
-
-    // Check if card is present
-    fn is_card_present(&self) -> bool {
-        let state = self.read_reg(EMMC_PRESENT_STATE);
-        // debug!("EMMC Present State: {:#b}", state);
-        (state & EMMC_CARD_INSERTED) != 0 && ((state & EMMC_CARD_STABLE) != 0)
-    }
-
-    // Check if card is write protected
-    fn is_write_protected(&self) -> bool {
-        let state = self.read_reg(EMMC_PRESENT_STATE);
-        (state & EMMC_WRITE_PROTECT) != 0
+        self.wait_for(EMMC_RESET_TIMEOUT, || {
+            Ok((self.read_reg8(EMMC_SOFTWARE_RESET) & mask) == 0)
+        })
     }
 
     // Initialize the eMMC card
@@ -249,12 +318,14 @@ impl EMmcHost {
         // CMD9: Read CSD (Card-Specific Data) register
         let csd = self.mmc_send_csd()?;
 
-        // Determine card version from CSD if unknown
+        // Decode CSD for frequency, size and block lengths
         let card = self.card.as_mut().unwrap();
+        let csd = Csd::decode(&csd, card.card_type);
+
+        // Determine card version from CSD if unknown
         if card.version() == MMC_VERSION_UNKNOWN {
-            let csd_version = (card.csd[0] >> 26) & 0xf;
-            debug!("eMMC CSD version: {}", csd_version);
-            match csd_version {
+            debug!("eMMC CSD version: {}", csd.spec_version);
+            match csd.spec_version {
                 0 => card.version = MMC_VERSION_1_2,
                 1 => card.version = MMC_VERSION_1_4,
                 2 => card.version = MMC_VERSION_2_2,
@@ -264,44 +335,19 @@ impl EMmcHost {
             }
         }
 
-        // Extract parameters from CSD for frequency, size, and block lengths
-        let (freq, mult, dsr_imp, mut read_bl_len, mut write_bl_len, csize, cmult) = {
-            let freq = FBASE[(csd[0] & 0x7) as usize];
-            let mult = MULTIPLIERS[((csd[0] >> 3) & 0xf) as usize];
-            let dsr_imp = (csd[1] >> 12) & 0x1;
-            let read_bl_len = (csd[1] >> 16) & 0xf;
-            let write_bl_len = (csd[3] >> 22) & 0xf;
-            let (csize, cmult) = if high_capacity {
-                ((csd[1] & 0x3f) << 16 | (csd[2] & 0xffff0000) >> 16, 8)
-            } else {
-                (
-                    (csd[1] & 0x3ff) << 2 | (csd[2] & 0xc0000000) >> 30,
-                    (csd[2] & 0x00038000) >> 15,
-                )
-            };
-            (freq, mult, dsr_imp, read_bl_len, write_bl_len, csize, cmult)
-        };
-
+        let dsr_imp = csd.dsr_imp as u32;
         card.dsr_imp = dsr_imp;
 
-        // Calculate user capacity
-        let _tran_speed = freq * mult as usize;
-        let mut capacity_user = (csize as u64 + 1) << (cmult as u64 + 2);
-        capacity_user *= read_bl_len as u64;
-        card.capacity_user = capacity_user;
+        // Calculate user capacity; devices above 2 GiB report it in EXT_CSD instead
+        let _tran_speed = csd.max_dtr();
+        card.capacity_user = csd.capacity_bytes();
 
         let mut capacity_gp = [0; 4];
 
         // Clip read/write block lengths to max supported size
-        if write_bl_len > MMC_MAX_BLOCK_LEN {
-            write_bl_len = MMC_MAX_BLOCK_LEN;
-        }
-        if read_bl_len > MMC_MAX_BLOCK_LEN {
-            read_bl_len = MMC_MAX_BLOCK_LEN;
-        }
-
-        card.read_bl_len = read_bl_len;
-        card.write_bl_len = write_bl_len;
+        let max_bl_len = generic_fls(MMC_MAX_BLOCK_LEN) - 1;
+        card.read_bl_len = (csd.read_bl_len as u32).min(max_bl_len);
+        card.write_bl_len = (csd.write_bl_len as u32).min(max_bl_len);
 
         // CMD4: Set DSR if required by card
         let dsr_needed = {
@@ -336,22 +382,35 @@ impl EMmcHost {
             card.version >= MMC_VERSION_4
         };
         if is_version_4_plus {
-            self.mmc_select_hs()?; // Switch to high speed
-            self.mmc_set_clock(MMC_HIGH_52_MAX_DTR); // Set high-speed clock
+            // Switch to high speed if the host and board allow it
+            if self.host_caps & MMC_MODE_HS != 0 {
+                self.mmc_select_hs()?;
+                let clock = if self.host_caps & MMC_MODE_HS_52MHZ != 0 {
+                    MMC_HIGH_52_MAX_DTR
+                } else {
+                    MMC_HIGH_26_MAX_DTR
+                };
+                self.mmc_set_clock(clock)?;
+            }
 
             // Allocate buffer for EXT_CSD read
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "dma")] {
-                    let mut ext_csd: DVec<u8> = DVec::zeros(MMC_MAX_BLOCK_LEN as usize, 0x1000, Direction::FromDevice).unwrap();
-                } else if #[cfg(feature = "pio")] {
-                    let mut ext_csd: [u8; 512] = [0; 512];
-                }
-            }
+            let mut ext_csd: [u8; 512] = [0; 512];
 
             // CMD8: Read EXT_CSD
             self.mmc_send_ext_csd(&mut ext_csd)?;
             let mut ext_csd = ext_csd.to_vec();
             trace!("EXT_CSD: {:?}", ext_csd);
+            self.set_ext_csd_rev(ext_csd[EXT_CSD_REV as usize]).unwrap();
+
+            // Busy times for CMD6, both in units of 10ms
+            if ext_csd[EXT_CSD_REV as usize] >= 5 {
+                self.set_part_switch_time(ext_csd[EXT_CSD_PART_SWITCH_TIME as usize] as u32 * 10)
+                    .unwrap();
+            }
+            if ext_csd[EXT_CSD_REV as usize] >= 6 {
+                self.set_generic_cmd6_time(ext_csd[EXT_CSD_GENERIC_CMD6_TIME as usize] as u32 * 10)
+                    .unwrap();
+            }
 
             // Extract capacity and version
             if ext_csd[EXT_CSD_REV as usize] >= 2 {
@@ -401,9 +460,15 @@ impl EMmcHost {
 
             // Check secure erase support
             if ext_csd[EXT_CSD_SEC_FEATURE_SUPPORT as usize] as u32 & EXT_CSD_SEC_GB_CL_EN != 0 {
-                let _mmc_can_trim = 1;
+                self.set_can_trim(true).unwrap();
             }
 
+            // Erase and trim timeouts are given in units of 300ms
+            self.set_erase_timeout(ext_csd[EXT_CSD_ERASE_TIMEOUT_MULT as usize] as u32 * 300)
+                .unwrap();
+            self.set_trim_timeout(ext_csd[EXT_CSD_TRIM_MULT as usize] as u32 * 300)
+                .unwrap();
+
             // Calculate boot and RPMB sizes
             let capacity_boot = (ext_csd[EXT_CSD_BOOT_MULT as usize] as u64) << 17;
             self.set_capacity_boot(capacity_boot).unwrap();
@@ -491,10 +556,7 @@ impl EMmcHost {
                         .unwrap();
                 }
             } else {
-                let erase_gsz = (csd[2] & 0x00007c00) >> 10;
-                let erase_gmul = (csd[2] & 0x000003e0) >> 5;
-                self.set_erase_grp_size((erase_gsz + 1) * (erase_gmul + 1))
-                    .unwrap();
+                self.set_erase_grp_size(csd.erase_group_sectors()).unwrap();
             }
 
             // Set high-capacity write-protect group size
@@ -508,6 +570,20 @@ impl EMmcHost {
                 .unwrap();
             self.set_raw_driver_strength(ext_csd[EXT_CSD_DRIVER_STRENGTH as usize])
                 .unwrap();
+
+            // Background operations support and current enable state
+            self.set_bkops_support(
+                ext_csd[EXT_CSD_BKOPS_SUPPORT as usize] & EXT_CSD_BKOPS_SUPPORTED != 0,
+            )
+            .unwrap();
+            self.set_bkops_en(ext_csd[EXT_CSD_BKOPS_EN as usize])
+                .unwrap();
+
+            self.set_rst_n_function(ext_csd[EXT_CSD_RST_N_FUNCTION as usize])
+                .unwrap();
+
+            // High priority interrupt, needed to preempt BKOPS
+            self.mmc_init_hpi(&ext_csd)?;
         }
 
         // Final initialization steps
@@ -518,46 +594,32 @@ impl EMmcHost {
         Ok(())
     }
 
-    fn mmc_set_capacity(&mut self, part_num: u32) -> Result<(), SdError> {
-        // part_num 暂时设置为 0
+    /// Size in bytes of hardware partition `part_num`, a PARTITION_ACCESS value
+    fn mmc_part_capacity(&self, part_num: u32) -> Option<u64> {
         match part_num {
-            0 => match self.capacity_user() {
-                Some(capacity_user) => self.set_capacity(capacity_user).unwrap(),
-                None => return Err(SdError::InvalidArgument),
-            },
-            1 | 2 => match self.capacity_boot() {
-                Some(capacity_boot) => self.set_capacity(capacity_boot).unwrap(),
-                None => return Err(SdError::InvalidArgument),
-            },
-            3 => match self.capacity_rpmb() {
-                Some(capacity_rpmb) => self.set_capacity(capacity_rpmb).unwrap(),
-                None => return Err(SdError::InvalidArgument),
-            },
-            4..=7 => match self.capacity_gp() {
-                Some(capacity_gp) => self
-                    .set_capacity(capacity_gp[(part_num - 4) as usize])
-                    .unwrap(),
-                None => return Err(SdError::InvalidArgument),
-            },
-            _ => return Err(SdError::InvalidArgument),
+            0 => self.capacity_user(),
+            1 | 2 => self.capacity_boot(),
+            3 => self.capacity_rpmb(),
+            4..=7 => self.capacity_gp().map(|gp| gp[(part_num - 4) as usize]),
+            _ => None,
         }
+    }
+
+    fn mmc_set_capacity(&mut self, part_num: u32) -> Result<(), SdError> {
+        let capacity = self
+            .mmc_part_capacity(part_num)
+            .ok_or(SdError::InvalidArgument)?;
 
-        let capacity = self.capacity().unwrap_or(0);
-        let read_bl_len = self.read_bl_len().unwrap_or(0);
-        let _lba = lldiv(capacity, read_bl_len);
+        self.set_capacity(capacity).unwrap();
+        self.set_capacity_blocks(lldiv(capacity, MMC_MAX_BLOCK_LEN))
+            .unwrap();
 
         Ok(())
     }
 
     pub fn mmc_change_freq(&mut self) -> Result<(), SdError> {
         // Allocate buffer for EXT_CSD depending on whether DMA or PIO is enabled
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "dma")] {
-                let mut ext_csd: DVec<u8> = DVec::zeros(MMC_MAX_BLOCK_LEN as usize, 0x1000, Direction::FromDevice).unwrap();
-            } else if #[cfg(feature = "pio")] {
-                let mut ext_csd: [u8; 512] = [0; 512];
-            }
-        }
+        let mut ext_csd: [u8; 512] = [0; 512];
 
         // Initialize card capabilities flags
         self.set_card_caps(0).unwrap();
@@ -587,14 +649,17 @@ impl EMmcHost {
             // Standard high-speed mode
             self.mmc_select_hs()
         } else {
-            Err(SdError::InvalidArgument)
+            debug!("No high speed mode shared with the host, staying in legacy timing");
+            Ok(())
         };
 
         // Apply the result of speed mode selection
         result?;
 
-        // Configure the bus speed according to selected type
-        self.mmc_set_bus_speed(avail_type as u32);
+        // Configure the bus speed according to selected type, legacy keeps its clock
+        if self.mmc_card_hs() || self.mmc_card_hs200() {
+            self.mmc_set_bus_speed(avail_type as u32)?;
+        }
 
         // If HS200 mode was selected, perform tuning procedure
         if self.mmc_card_hs200() {
@@ -605,32 +670,32 @@ impl EMmcHost {
                 && self.bus_width().unwrap_or(0) == MMC_BUS_WIDTH_8BIT
             {
                 // self.mmc_select_hs400()?; // Currently not executed
-                self.mmc_set_bus_speed(avail_type as u32);
+                self.mmc_set_bus_speed(avail_type as u32)?;
             }
 
             tuning_result
         } else if !self.mmc_card_hs400es() {
             // If not in HS400 Enhanced Strobe mode, try to switch bus width
             let width_result = self.mmc_select_bus_width()?;
-            let err = if width_result > 0 {
-                Ok(())
-            } else {
-                Err(SdError::BusWidth)
-            };
+            if width_result == 0 {
+                // No wider bus shared with the host, stay 1-bit
+                debug!("Staying on a 1-bit bus");
+                return Ok(());
+            }
 
             // If DDR52 mode is supported, implement selection (currently TODO)
-            if err.is_ok() && avail_type & EXT_CSD_CARD_TYPE_DDR_52 as u16 != 0 {
+            if avail_type & EXT_CSD_CARD_TYPE_DDR_52 as u16 != 0 {
                 todo!("Implement HS-DDR selection");
             }
 
-            err
+            Ok(())
         } else {
             // Already in HS400ES mode, no further action needed
             Ok(())
         }
     }
 
-    pub fn mmc_set_bus_speed(&mut self, avail_type: u32) {
+    pub fn mmc_set_bus_speed(&mut self, avail_type: u32) -> Result<(), SdError> {
         let mut clock = 0;
 
         if self.mmc_card_hs() {
@@ -643,7 +708,7 @@ impl EMmcHost {
             clock = MMC_HS200_MAX_DTR;
         }
 
-        self.mmc_set_clock(clock);
+        self.mmc_set_clock(clock)
     }
 
     /// 检查卡是否为HS模式
@@ -674,7 +739,7 @@ impl EMmcHost {
                 false,
             )?;
 
-            self.mmc_set_timing(MMC_TIMING_MMC_HS200);
+            self.mmc_set_timing(MMC_TIMING_MMC_HS200)?;
         }
 
         Ok(())
@@ -684,16 +749,8 @@ impl EMmcHost {
         let ext_csd_bits: [u8; 2] = [EXT_CSD_BUS_WIDTH_8, EXT_CSD_BUS_WIDTH_4];
         let bus_widths: [u8; 2] = [MMC_BUS_WIDTH_8BIT, MMC_BUS_WIDTH_4BIT];
 
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "dma")] {
-                let mut ext_csd: DVec<u8> = DVec::zeros(MMC_MAX_BLOCK_LEN as usize, 0x1000, Direction::FromDevice).unwrap();
-                let mut test_csd = DVec::zeros(MMC_MAX_BLOCK_LEN as usize, 0x1000, Direction::FromDevice)
-        .ok_or(SdError::MemoryError)?;
-            } else if #[cfg(feature = "pio")] {
-                let mut ext_csd: [u8; 512] = [0; 512];
-                let mut test_csd: [u8; 512] = [0; 512];
-            }
-        }
+        let mut ext_csd: [u8; 512] = [0; 512];
+        let mut test_csd: [u8; 512] = [0; 512];
 
         // 版本检查和主机能力检查
         if self.version().unwrap_or(0) < MMC_VERSION_4
@@ -723,7 +780,7 @@ impl EMmcHost {
             }
 
             let bus_width = bus_widths[idx];
-            self.mmc_set_bus_width(bus_width);
+            self.mmc_set_bus_width(bus_width)?;
 
             // 再次读取EXT_CSD进行验证
             let test_result = self.mmc_send_ext_csd(&mut test_csd);
@@ -750,18 +807,6 @@ impl EMmcHost {
         Err(SdError::BadMessage)
     }
 
-    #[cfg(feature = "dma")]
-    fn compare_sector_count(&self, ext_csd: &DVec<u8>, test_csd: &DVec<u8>) -> bool {
-        let sec_cnt_offset = EXT_CSD_SEC_CNT as usize;
-        for i in 0..4 {
-            if ext_csd[sec_cnt_offset + i] != test_csd[sec_cnt_offset + i] {
-                return false;
-            }
-        }
-        true
-    }
-
-    #[cfg(feature = "pio")]
     fn compare_sector_count(&self, ext_csd: &[u8], test_csd: &[u8]) -> bool {
         let sec_cnt_offset = EXT_CSD_SEC_CNT as usize;
         for i in 0..4 {
@@ -864,51 +909,6 @@ impl EMmcHost {
             || (timing == MMC_TIMING_MMC_HS400ES)
     }
 
-    #[cfg(feature = "dma")]
-    pub fn mmc_select_card_type(&self, ext_csd: &DVec<u8>) -> u16 {
-        let card_type = ext_csd[EXT_CSD_CARD_TYPE as usize] as u16;
-        let host_caps = self.host_caps;
-        let mut avail_type = 0;
-
-        if (host_caps & MMC_MODE_HS != 0) && (card_type & EXT_CSD_CARD_TYPE_26 != 0) {
-            avail_type |= EXT_CSD_CARD_TYPE_26;
-        }
-
-        if (host_caps & MMC_MODE_HS != 0) && (card_type & EXT_CSD_CARD_TYPE_52 != 0) {
-            avail_type |= EXT_CSD_CARD_TYPE_52;
-        }
-
-        if (host_caps & MMC_MODE_DDR_52MHZ != 0)
-            && (card_type & EXT_CSD_CARD_TYPE_DDR_1_8V as u16 != 0)
-        {
-            avail_type |= EXT_CSD_CARD_TYPE_DDR_1_8V as u16;
-        }
-
-        if (host_caps & MMC_MODE_HS200 != 0) && (card_type & EXT_CSD_CARD_TYPE_HS200_1_8V != 0) {
-            avail_type |= EXT_CSD_CARD_TYPE_HS200_1_8V;
-        }
-
-        if (host_caps & MMC_MODE_HS400 != 0)
-            && (host_caps & MMC_MODE_8BIT != 0)
-            && (card_type & EXT_CSD_CARD_TYPE_HS400_1_8V != 0)
-        {
-            avail_type |= EXT_CSD_CARD_TYPE_HS200_1_8V | EXT_CSD_CARD_TYPE_HS400_1_8V;
-        }
-
-        if (host_caps & MMC_MODE_HS400ES != 0)
-            && (host_caps & MMC_MODE_8BIT != 0)
-            && (ext_csd[EXT_CSD_STROBE_SUPPORT as usize] != 0)
-            && (avail_type & EXT_CSD_CARD_TYPE_HS400_1_8V != 0)
-        {
-            avail_type |= EXT_CSD_CARD_TYPE_HS200_1_8V
-                | EXT_CSD_CARD_TYPE_HS400_1_8V
-                | EXT_CSD_CARD_TYPE_HS400ES;
-        }
-
-        avail_type
-    }
-
-    #[cfg(feature = "pio")]
     pub fn mmc_select_card_type(&self, ext_csd: &[u8]) -> u16 {
         let card_type = ext_csd[EXT_CSD_CARD_TYPE as usize] as u16;
         let host_caps = self.host_caps;
@@ -961,32 +961,35 @@ impl EMmcHost {
         );
 
         if ret.is_ok() {
-            self.mmc_set_timing(MMC_TIMING_MMC_HS);
+            self.mmc_set_timing(MMC_TIMING_MMC_HS)?;
         }
 
         ret
     }
 
-    fn mmc_set_bus_width(&mut self, width: u8) {
+    fn mmc_set_bus_width(&mut self, width: u8) -> Result<(), SdError> {
         /* Set bus width */
         let card = self.card.as_mut().unwrap();
         card.bus_width = width;
         debug!("Bus width set to {}", width);
-        self.sdhci_set_ios();
+        self.sdhci_set_ios()
     }
 
-    fn mmc_set_timing(&mut self, timing: u32) {
+    fn mmc_set_timing(&mut self, timing: u32) -> Result<(), SdError> {
         /* Set timing */
         let card = self.card.as_mut().unwrap();
         card.timing = timing;
-        self.sdhci_set_ios();
+        self.sdhci_set_ios()
     }
 
-    fn mmc_set_clock(&mut self, clk: u32) {
+    fn mmc_set_clock(&mut self, mut clk: u32) -> Result<(), SdError> {
         /* Set clock */
+        if self.config.max_frequency != 0 {
+            clk = clk.min(self.config.max_frequency);
+        }
         let card = self.card.as_mut().unwrap();
         card.clock = clk;
-        self.sdhci_set_ios();
+        self.sdhci_set_ios()
     }
 
     fn mmc_switch(
@@ -1010,7 +1013,8 @@ impl EMmcHost {
 
             if ret.is_ok() {
                 debug!("cmd6 {:#x}", self.get_response().as_r1());
-                return self.mmc_poll_for_busy(send_status);
+                let timeout = self.mmc_switch_timeout(index);
+                return self.mmc_poll_for_busy_timeout(send_status, timeout, None);
             }
 
             retries -= 1;
@@ -1022,4 +1026,33 @@ impl EMmcHost {
 
         Err(SdError::Timeout)
     }
+
+    /// Select the hardware partition used for block access, `part` is a
+    /// PARTITION_ACCESS value (0 user, 1-2 boot, 3 RPMB, 4-7 GP). Block
+    /// access is bounded by the size of the selected partition afterwards.
+    pub fn mmc_switch_part(&mut self, part: u8) -> Result<(), SdError> {
+        if part as u32 > PART_ACCESS_MASK {
+            return Err(SdError::InvalidArgument);
+        }
+        self.mmc_reinit_if_needed()?;
+
+        // Only partitions the card actually has can be selected
+        if self
+            .mmc_part_capacity(part as u32)
+            .is_none_or(|capacity| capacity == 0)
+        {
+            return Err(SdError::PartitionNotFound);
+        }
+
+        let part_config = self.part_config().ok_or(SdError::NoCard)?;
+        if part_config == MMCPART_NOAVAILABLE {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        let value = (part_config & !(PART_ACCESS_MASK as u8)) | part;
+        self.mmc_switch(EXT_CSD_CMD_SET_NORMAL, EXT_CSD_PART_CONF, value, true)?;
+        self.set_part_config(value).unwrap();
+
+        self.mmc_set_capacity(part as u32)
+    }
 }
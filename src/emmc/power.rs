@@ -0,0 +1,171 @@
+use alloc::boxed::Box;
+
+use log::{debug, info, warn};
+
+use super::{EMmcHost, aux::generic_fls, constant::*};
+use crate::err::SdError;
+
+/// Accepted deviation when reading back a regulator voltage
+const REGULATOR_TOLERANCE_UV: u32 = 100_000;
+
+/// Board supply feeding the card, either card power (vmmc) or I/O (vqmmc)
+pub trait Regulator: Send {
+    fn enable(&self) -> Result<(), SdError>;
+    fn disable(&self) -> Result<(), SdError>;
+    fn set_voltage(&self, microvolts: u32) -> Result<(), SdError>;
+    fn get_voltage(&self) -> Result<u32, SdError>;
+}
+
+impl core::fmt::Debug for dyn Regulator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Regulator")
+    }
+}
+
+/// I/O signaling level of the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalVoltage {
+    V330,
+    V180,
+}
+
+impl SignalVoltage {
+    pub fn microvolts(&self) -> u32 {
+        match self {
+            SignalVoltage::V330 => 3_300_000,
+            SignalVoltage::V180 => 1_800_000,
+        }
+    }
+
+    /// Signaling level required by an `MMC_TIMING_*` mode
+    pub fn for_timing(timing: u32) -> Option<Self> {
+        match timing {
+            MMC_TIMING_LEGACY | MMC_TIMING_MMC_HS | MMC_TIMING_SD_HS => None,
+            _ => Some(SignalVoltage::V180),
+        }
+    }
+}
+
+impl EMmcHost {
+    /// Use `vmmc` as the card power supply
+    pub fn with_vmmc(mut self, vmmc: Box<dyn Regulator>) -> Self {
+        self.vmmc = Some(vmmc);
+        self
+    }
+
+    /// Use `vqmmc` as the card I/O supply
+    pub fn with_vqmmc(mut self, vqmmc: Box<dyn Regulator>) -> Self {
+        self.vqmmc = Some(vqmmc);
+        self
+    }
+
+    pub fn signal_voltage(&self) -> SignalVoltage {
+        self.signal_voltage
+    }
+
+    /// Power the card up at the highest voltage both host and card support,
+    /// with 3.3V signaling
+    pub fn power_up(&mut self) -> Result<(), SdError> {
+        if self.voltages == 0 {
+            return Err(SdError::UnsupportedCard);
+        }
+
+        let vdd = generic_fls(self.voltages) - 1;
+        if let Some(vmmc) = &self.vmmc {
+            vmmc.set_voltage(ocr_microvolts(1 << vdd))?;
+            vmmc.enable()?;
+        }
+
+        self.sdhci_set_power(vdd)?;
+
+        if let Some(vqmmc) = &self.vqmmc {
+            vqmmc.set_voltage(SignalVoltage::V330.microvolts())?;
+            vqmmc.enable()?;
+        }
+        let ctrl_2 = self.read_reg16(EMMC_HOST_CTRL2);
+        self.write_reg16(EMMC_HOST_CTRL2, ctrl_2 & !MMC_CTRL_VDD_180);
+        self.signal_voltage = SignalVoltage::V330;
+
+        // Supply ramp up before the first command
+        self.delay_us(1000)?;
+
+        Ok(())
+    }
+
+    /// Stop the bus clock and remove all card supplies
+    pub fn power_off(&mut self) -> Result<(), SdError> {
+        self.write_reg16(EMMC_CLOCK_CONTROL, 0);
+        self.clock = 0;
+
+        self.sdhci_set_power(0xFFFF)?;
+
+        if let Some(vqmmc) = &self.vqmmc {
+            vqmmc.disable()?;
+        }
+        if let Some(vmmc) = &self.vmmc {
+            vmmc.disable()?;
+        }
+
+        Ok(())
+    }
+
+    /// Full power cycle, leaves the card in idle state at 3.3V signaling
+    pub fn power_cycle(&mut self) -> Result<(), SdError> {
+        info!("Power cycling card");
+
+        self.power_off()?;
+        // Supplies must stay off long enough to fully discharge
+        self.delay_us(10000)?;
+        self.power_up()
+    }
+
+    /// Switch the bus signaling level and check the switch took effect
+    pub(crate) fn mmc_set_signal_voltage(&mut self, voltage: SignalVoltage) -> Result<(), SdError> {
+        if voltage == self.signal_voltage {
+            return Ok(());
+        }
+
+        debug!("Switching signal voltage to {:?}", voltage);
+
+        if let Some(vqmmc) = &self.vqmmc {
+            vqmmc.set_voltage(voltage.microvolts())?;
+        }
+
+        let mut ctrl_2 = self.read_reg16(EMMC_HOST_CTRL2);
+        match voltage {
+            SignalVoltage::V180 => ctrl_2 |= MMC_CTRL_VDD_180,
+            SignalVoltage::V330 => ctrl_2 &= !MMC_CTRL_VDD_180,
+        }
+        self.write_reg16(EMMC_HOST_CTRL2, ctrl_2);
+
+        // Regulator output must be stable within 5ms
+        self.delay_us(5000)?;
+
+        // The controller drops the 1.8V enable if its regulator failed to switch
+        let ctrl_2 = self.read_reg16(EMMC_HOST_CTRL2);
+        if ((ctrl_2 & MMC_CTRL_VDD_180) != 0) != (voltage == SignalVoltage::V180) {
+            warn!("Host did not switch signaling to {:?}", voltage);
+            return Err(SdError::VoltageSwitchFailed);
+        }
+
+        if let Some(vqmmc) = &self.vqmmc {
+            let actual = vqmmc.get_voltage()?;
+            if actual.abs_diff(voltage.microvolts()) > REGULATOR_TOLERANCE_UV {
+                warn!("vqmmc at {}uV after switching to {:?}", actual, voltage);
+                return Err(SdError::VoltageSwitchFailed);
+            }
+        }
+
+        self.signal_voltage = voltage;
+        Ok(())
+    }
+}
+
+/// Supply voltage in microvolts for a single `MMC_VDD_*` OCR bit
+fn ocr_microvolts(vdd: u32) -> u32 {
+    match vdd {
+        MMC_VDD_165_195 => 1_800_000,
+        MMC_VDD_29_30 | MMC_VDD_30_31 => 3_000_000,
+        _ => 3_300_000,
+    }
+}
@@ -0,0 +1,263 @@
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use log::{debug, info, warn};
+use spin::Mutex;
+
+use super::{
+    CardState, CardStatus, CardType, EMMC_BUSY_POLL_US, EMmcHost, block::EMmcCard,
+    cmd::EMmcCommand, constant::*,
+};
+use crate::err::SdError;
+
+/// Times a failed transfer is retried after recovering the card
+pub const EMMC_IO_RETRIES: u32 = 3;
+
+/// Deepest recovery step taken to bring the card back to transfer state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryAction {
+    /// The card was still in transfer state, only CMD/DAT lines were reset
+    LineReset,
+    /// An ongoing transfer was aborted with CMD12
+    Abort,
+    /// The card had to be re-initialized from scratch
+    Reinit,
+    /// The card was reset through RST_n before re-initializing
+    HwReset,
+}
+
+/// Recovery counters since the host was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryStats {
+    /// Transfers retried after an error
+    pub retries: u32,
+    /// CMD12 abort commands issued
+    pub aborts: u32,
+    /// Full card re-initializations
+    pub reinits: u32,
+    /// Recoveries that left the card unusable
+    pub failures: u32,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RecoveryState {
+    retries: AtomicU32,
+    aborts: AtomicU32,
+    reinits: AtomicU32,
+    failures: AtomicU32,
+    /// Set when the card could not be brought back without `init_card`,
+    /// holds the error that led there
+    reinit_cause: Mutex<Option<SdError>>,
+}
+
+impl RecoveryState {
+    /// A freshly initialized card needs no recovery
+    pub(crate) fn card_initialized(&self) {
+        *self.reinit_cause.lock() = None;
+    }
+
+    fn failed(&self, cause: SdError) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        self.reinit_cause.lock().get_or_insert(cause);
+    }
+}
+
+impl EMmcHost {
+    /// Retry failed transfers up to `retries` times after recovering the card
+    pub fn with_io_retries(mut self, retries: u32) -> Self {
+        self.io_retries = retries;
+        self
+    }
+
+    pub fn set_io_retries(&mut self, retries: u32) {
+        self.io_retries = retries;
+    }
+
+    pub fn io_retries(&self) -> u32 {
+        self.io_retries
+    }
+
+    pub fn recovery_stats(&self) -> RecoveryStats {
+        RecoveryStats {
+            retries: self.recovery.retries.load(Ordering::Relaxed),
+            aborts: self.recovery.aborts.load(Ordering::Relaxed),
+            reinits: self.recovery.reinits.load(Ordering::Relaxed),
+            failures: self.recovery.failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The last recovery failed, the card only comes back with `recover`.
+    /// Meanwhile transfers fail with `SdError::NeedsReinit`, while
+    /// operations taking `&mut self` such as `health` or `mmc_switch_part`
+    /// re-initialize the card by themselves.
+    pub fn needs_reinit(&self) -> bool {
+        self.recovery.reinit_cause.lock().is_some()
+    }
+
+    /// Bring the card back to transfer state after an error.
+    ///
+    /// Resets the CMD/DAT lines, aborts any transfer the card is still in and
+    /// polls CMD13 until the card is back in transfer state. When that fails
+    /// the card is re-initialized, and reset through RST_n first if it does
+    /// not come back otherwise and RST_n is enabled.
+    pub fn recover(&mut self) -> Result<RecoveryAction, SdError> {
+        if !self.needs_reinit() {
+            match self.mmc_recover() {
+                Ok(action) => return Ok(action),
+                Err(e) => warn!("Card recovery failed: {:?}", e),
+            }
+        }
+
+        // Re-initialization forgets the card, remember whether RST_n works
+        let hw_reset = self.hw_reset_enabled();
+        match self.mmc_reinit() {
+            Ok(()) => Ok(RecoveryAction::Reinit),
+            Err(e) if hw_reset => {
+                warn!("Card re-initialization failed: {:?}", e);
+                self.hw_reset()?;
+                Ok(RecoveryAction::HwReset)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Run `op` and retry it after recovering the card from transient errors.
+    ///
+    /// Only recovery steps that do not need exclusive access are taken here.
+    /// If those fail `SdError::NeedsReinit` is returned and `needs_reinit`
+    /// reports true.
+    pub(crate) fn mmc_retry(
+        &self,
+        mut op: impl FnMut() -> Result<(), SdError>,
+    ) -> Result<(), SdError> {
+        if let Some(cause) = self.recovery.reinit_cause.lock().clone() {
+            return Err(SdError::NeedsReinit(Box::new(cause)));
+        }
+
+        let mut attempt = 0;
+        loop {
+            let err = match op() {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            if !err.is_retryable() {
+                return Err(err);
+            }
+
+            // Leave the card in transfer state even when giving up
+            if let Err(e) = self.mmc_recover_tran() {
+                warn!("Card recovery after {:?} failed: {:?}", err, e);
+                self.recovery.failed(err.clone());
+                return Err(SdError::NeedsReinit(Box::new(err)));
+            }
+
+            if attempt >= self.io_retries {
+                return Err(err);
+            }
+            attempt += 1;
+            self.recovery.retries.fetch_add(1, Ordering::Relaxed);
+            info!(
+                "Retrying transfer after {:?} ({}/{})",
+                err, attempt, self.io_retries
+            );
+        }
+    }
+
+    /// Reset the CMD/DAT lines and return the card to transfer state
+    pub(crate) fn mmc_recover(&self) -> Result<RecoveryAction, SdError> {
+        let result = self.mmc_recover_tran();
+        if let Err(e) = &result {
+            self.recovery.failed(e.clone());
+        }
+        result
+    }
+
+    /// Re-initialize the card first if an earlier recovery left it unusable
+    pub(crate) fn mmc_reinit_if_needed(&mut self) -> Result<(), SdError> {
+        if self.needs_reinit() {
+            self.recover()?;
+        }
+        Ok(())
+    }
+
+    fn mmc_recover_tran(&self) -> Result<RecoveryAction, SdError> {
+        self.card.as_ref().ok_or(SdError::NoCard)?;
+
+        self.reset_cmd()?;
+        self.reset_data()?;
+
+        let mut action = RecoveryAction::LineReset;
+        let deadline = self.deadline(self.timeouts.busy)?;
+        loop {
+            let state = self.mmc_status_for_recovery()?.state();
+            debug!("Recovery: card in {:?}", state);
+
+            match state {
+                CardState::Tran => {
+                    self.mmc_set_card_state(CardState::Tran);
+                    return Ok(action);
+                }
+                CardState::Data | CardState::Rcv => {
+                    // Check the state again either way, the transfer may have ended meanwhile
+                    if let Err(e) = self.mmc_abort() {
+                        debug!("Abort failed: {:?}", e);
+                    }
+                    action = RecoveryAction::Abort;
+                }
+                CardState::Prg => {}
+                _ => {
+                    warn!("Card in {:?} state, cannot resume transfers", state);
+                    return Err(SdError::InvalidResponse);
+                }
+            }
+
+            if deadline.expired() {
+                return Err(SdError::Timeout);
+            }
+            self.delay_us(EMMC_BUSY_POLL_US)?;
+        }
+    }
+
+    /// Stop the transfer in progress with CMD12 as an abort command
+    pub(crate) fn mmc_abort(&self) -> Result<(), SdError> {
+        self.recovery.aborts.fetch_add(1, Ordering::Relaxed);
+
+        let cmd = EMmcCommand::new(MMC_STOP_TRANSMISSION, 0, MMC_RSP_R1B).with_abort();
+        let result = self.send_command(&cmd, None);
+
+        // The host controller must reset both lines after an abort command
+        self.reset_cmd()?;
+        self.reset_data()?;
+
+        result
+    }
+
+    /// CMD13 without R1 error checking, errors from the failed transfer may
+    /// still be reported
+    fn mmc_status_for_recovery(&self) -> Result<CardStatus, SdError> {
+        let rca = self.card.as_ref().ok_or(SdError::NoCard)?.rca;
+        let cmd = EMmcCommand::new(MMC_SEND_STATUS, rca << 16, MMC_RSP_R1);
+        match self.send_command(&cmd, None) {
+            Ok(()) => Ok(CardStatus::from(self.get_response().as_r1())),
+            Err(e) => match e.kind() {
+                SdError::CardError { status, .. } => Ok(*status),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Re-initialize the card from power up, keeping the host configuration
+    pub fn mmc_reinit(&mut self) -> Result<(), SdError> {
+        warn!("Re-initializing card");
+        self.recovery.reinits.fetch_add(1, Ordering::Relaxed);
+
+        self.reset(EMMC_RESET_CMD | EMMC_RESET_DATA)?;
+
+        self.add_card(EMmcCard::init(CardType::Unknown));
+        self.mmc_init_bus()?;
+        self.init_card()?;
+
+        self.recovery.card_initialized();
+        Ok(())
+    }
+}
@@ -1,34 +1,29 @@
-use super::{EMmcHost, constant::*};
-use crate::{
-    delay_us,
-    emmc::{aux::dll_lock_wo_tmout, clock::emmc_set_clk, config::EMmcChipConfig},
-    err::SdError,
+use super::{
+    EMMC_CLOCK_STABLE_TIMEOUT, EMMC_DLL_LOCK_TIMEOUT, EMMC_INHIBIT_TIMEOUT, EMmcHost,
+    SignalVoltage, aux::dll_lock_wo_tmout, constant::*,
 };
+use crate::err::SdError;
 use log::{debug, info};
 
 impl EMmcHost {
     // Rockchip EMMC设置时钟函数
     pub fn rockchip_emmc_set_clock(&mut self, freq: u32) -> Result<(), SdError> {
         // wait for command and data inhibit to be cleared
-        let mut timeout = 20;
-        while (self.read_reg(EMMC_PRESENT_STATE) & (EMMC_CMD_INHIBIT | EMMC_DATA_INHIBIT)) != 0 {
-            if timeout == 0 {
-                debug!("Timeout waiting for cmd & data inhibit");
-                return Err(SdError::Timeout);
-            }
-            timeout -= 1;
-            delay_us(1000);
-        }
+        self.wait_for(EMMC_INHIBIT_TIMEOUT, || {
+            Ok((self.read_reg(EMMC_PRESENT_STATE) & (EMMC_CMD_INHIBIT | EMMC_DATA_INHIBIT)) == 0)
+        })
+        .inspect_err(|_| debug!("Timeout waiting for cmd & data inhibit"))?;
 
         // first disable the clock
         self.write_reg16(EMMC_CLOCK_CONTROL, 0x0000);
 
         if freq == 0 {
+            self.clock = 0;
             return Ok(());
         }
 
         // 计算输入时钟
-        let input_clk = emmc_set_clk(freq as u64).unwrap() as u32;
+        let input_clk = self.mmc_set_input_clk(freq as u64)? as u32;
         info!("input_clk: {}", input_clk);
 
         let mut div = 0;
@@ -77,6 +72,16 @@ impl EMmcHost {
 
         info!("EMMC Clock Divisor: 0x{:x}", div);
 
+        // Record the card clock this divisor actually produces
+        self.clock = if (clk & EMMC_PROG_CLOCK_MODE) != 0 {
+            input_clk / (div + 1)
+        } else if div == 0 {
+            input_clk
+        } else {
+            input_clk / (2 * div)
+        };
+        info!("EMMC card clock: {} Hz (requested {} Hz)", self.clock, freq);
+
         clk |= ((div as u16) & 0xFF) << EMMC_DIVIDER_SHIFT;
         clk |= (((div as u16) & 0x300) >> 8) << EMMC_DIVIDER_HI_SHIFT;
 
@@ -91,15 +96,10 @@ impl EMmcHost {
         clk &= !EMMC_CLOCK_INT_STABLE;
         self.write_reg16(EMMC_CLOCK_CONTROL, clk);
 
-        let mut timeout = 20;
-        while (self.read_reg16(EMMC_CLOCK_CONTROL) & EMMC_CLOCK_INT_STABLE) == 0 {
-            timeout -= 1;
-            delay_us(1000);
-            if timeout == 0 {
-                info!("Internal clock never stabilised.");
-                return Err(SdError::Timeout);
-            }
-        }
+        self.wait_for(EMMC_CLOCK_STABLE_TIMEOUT, || {
+            Ok((self.read_reg16(EMMC_CLOCK_CONTROL) & EMMC_CLOCK_INT_STABLE) != 0)
+        })
+        .inspect_err(|_| info!("Internal clock never stabilised."))?;
 
         self.write_reg16(EMMC_CLOCK_CONTROL, clk | EMMC_CLOCK_CARD_EN);
 
@@ -145,16 +145,15 @@ impl EMmcHost {
         info!("EMMC Power Control: {:#x}", self.read_reg8(EMMC_POWER_CTRL));
 
         // Small delay for power to stabilize
-        delay_us(10000);
+        self.delay_us(10000)?;
 
         Ok(())
     }
 
     // DWCMSHC SDHCI EMMC设置时钟
     pub fn dwcmshc_sdhci_emmc_set_clock(&mut self, freq: u32) -> Result<(), SdError> {
-        let mut timeout = 500;
         let timing = self.card.as_ref().unwrap().timing;
-        let data = EMmcChipConfig::rk3568_config();
+        let data = self.config;
 
         self.rockchip_emmc_set_clock(freq)?;
         // Disable output clock while config DLL
@@ -169,7 +168,7 @@ impl EMmcHost {
         if freq >= 100_000_000 {
             // Enable DLL
             self.write_reg(DWCMSHC_EMMC_DLL_CTRL, DWCMSHC_EMMC_DLL_CTRL_RESET);
-            delay_us(1000);
+            self.delay_us(1000)?;
             self.write_reg(DWCMSHC_EMMC_DLL_CTRL, 0);
             let mut extra = 0x1 << 16 | 0x2 << 17 | 0x3 << 19;
             self.write_reg(DWCMSHC_EMMC_ATCTRL, extra);
@@ -180,19 +179,10 @@ impl EMmcHost {
                 | DWCMSHC_EMMC_DLL_START;
             self.write_reg(DWCMSHC_EMMC_DLL_CTRL, extra);
 
-            loop {
-                if timeout <= 0 {
-                    info!("Timeout waiting for DLL to be ready");
-                    return Err(SdError::Timeout);
-                }
-
-                if dll_lock_wo_tmout(self.read_reg(DWCMSHC_EMMC_DLL_STATUS0)) {
-                    break;
-                }
-
-                delay_us(1000);
-                timeout -= 1;
-            }
+            self.wait_for(EMMC_DLL_LOCK_TIMEOUT, || {
+                Ok(dll_lock_wo_tmout(self.read_reg(DWCMSHC_EMMC_DLL_STATUS0)))
+            })
+            .inspect_err(|_| info!("Timeout waiting for DLL to be ready"))?;
 
             let dll_lock_value = ((self.read_reg(DWCMSHC_EMMC_DLL_STATUS0) & 0xFF) * 2) & 0xFF;
 
@@ -281,15 +271,9 @@ impl EMmcHost {
         let timing = self.card.as_ref().unwrap().timing;
 
         let mut ctrl_2 = self.read_reg16(EMMC_HOST_CTRL2);
+        // The 1.8V enable is owned by mmc_set_signal_voltage
         ctrl_2 &= !MMC_CTRL_UHS_MASK;
 
-        if (timing != MMC_TIMING_LEGACY)
-            && (timing != MMC_TIMING_MMC_HS)
-            && (timing != MMC_TIMING_SD_HS)
-        {
-            ctrl_2 |= MMC_CTRL_VDD_180;
-        }
-
         if (timing == MMC_TIMING_MMC_HS200) || (timing == MMC_TIMING_UHS_SDR104) {
             ctrl_2 |= MMC_CTRL_UHS_SDR104 | MMC_CTRL_DRV_TYPE_A;
         } else if timing == MMC_TIMING_UHS_SDR12 {
@@ -309,7 +293,7 @@ impl EMmcHost {
         self.write_reg16(EMMC_HOST_CTRL2, ctrl_2);
     }
 
-    pub fn sdhci_set_ios(&mut self) {
+    pub fn sdhci_set_ios(&mut self) -> Result<(), SdError> {
         let (card_clock, bus_width, timing) = {
             let card = self.card.as_ref().unwrap();
             (card.clock, card.bus_width, card.timing)
@@ -320,7 +304,7 @@ impl EMmcHost {
             card_clock, bus_width, timing
         );
 
-        self.dwcmshc_sdhci_emmc_set_clock(card_clock).unwrap();
+        self.dwcmshc_sdhci_emmc_set_clock(card_clock)?;
 
         /* Set bus width */
         let mut ctrl = self.read_reg8(EMMC_HOST_CTRL1);
@@ -354,12 +338,13 @@ impl EMmcHost {
 
         self.write_reg8(EMMC_HOST_CTRL1, ctrl);
 
-        if timing != MMC_TIMING_LEGACY && timing != MMC_TIMING_MMC_HS && timing != MMC_TIMING_SD_HS
-        {
-            self.sdhci_set_power(MMC_VDD_165_195_SHIFT).unwrap();
+        if let Some(voltage) = SignalVoltage::for_timing(timing) {
+            self.mmc_set_signal_voltage(voltage)?;
         }
 
         self.sdhci_set_uhs_signaling();
+
+        Ok(())
     }
 
     fn sdhci_get_version(&self) -> u16 {
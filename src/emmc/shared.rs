@@ -0,0 +1,99 @@
+use kspin::{SpinNoIrq, SpinNoIrqGuard};
+
+use super::EMmcHost;
+use crate::{block::BlockDevice, err::SdError};
+
+/// `EMmcHost` shared between cores.
+///
+/// Every command sequence runs with the host locked and local interrupts
+/// disabled, so callers on different cores are serialized and a transfer
+/// can never be interleaved with another command on the bus.
+///
+/// The host therefore never sleeps or yields while shared, all waits spin,
+/// busy waits and re-initialization included. Where that latency matters,
+/// put the `EMmcHost` behind a sleeping lock of the kernel instead.
+pub struct SharedEMmcHost {
+    host: SpinNoIrq<EMmcHost>,
+}
+
+impl SharedEMmcHost {
+    pub fn new(mut host: EMmcHost) -> Self {
+        host.set_atomic(true);
+        Self {
+            host: SpinNoIrq::new(host),
+        }
+    }
+
+    /// Lock the host for a sequence of commands that must not be interleaved
+    pub fn lock(&self) -> SpinNoIrqGuard<'_, EMmcHost> {
+        self.host.lock()
+    }
+
+    /// Lock the host unless another core is using it
+    pub fn try_lock(&self) -> Option<SpinNoIrqGuard<'_, EMmcHost>> {
+        self.host.try_lock()
+    }
+
+    /// Run `f` with exclusive access to the host
+    pub fn with<R>(&self, f: impl FnOnce(&mut EMmcHost) -> R) -> R {
+        f(&mut self.host.lock())
+    }
+
+    pub fn init(&self) -> Result<(), SdError> {
+        self.host.lock().init()
+    }
+
+    pub fn into_inner(self) -> EMmcHost {
+        let mut host = self.host.into_inner();
+        host.set_atomic(false);
+        host
+    }
+
+    /// Run `op`, re-initializing the card and trying once more if recovery
+    /// without exclusive access failed
+    fn with_reinit(
+        &self,
+        mut op: impl FnMut(&EMmcHost) -> Result<(), SdError>,
+    ) -> Result<(), SdError> {
+        let mut host = self.host.lock();
+        match op(&host) {
+            Err(_) if host.needs_reinit() => {
+                host.recover()?;
+                op(&host)
+            }
+            result => result,
+        }
+    }
+}
+
+impl From<EMmcHost> for SharedEMmcHost {
+    fn from(host: EMmcHost) -> Self {
+        Self::new(host)
+    }
+}
+
+impl BlockDevice for SharedEMmcHost {
+    fn num_blocks(&self) -> u64 {
+        self.host.lock().num_blocks()
+    }
+
+    fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<(), SdError> {
+        self.with_reinit(|host| host.read(block_id, buf))
+    }
+
+    fn write(&self, block_id: u64, buf: &[u8]) -> Result<(), SdError> {
+        self.with_reinit(|host| host.write(block_id, buf))
+    }
+
+    fn flush(&self) -> Result<(), SdError> {
+        self.host.lock().flush()
+    }
+
+    fn discard(&self, block_id: u64, blocks: u64) -> Result<(), SdError> {
+        self.host.lock().discard(block_id, blocks)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.host.lock().is_read_only()
+    }
+}
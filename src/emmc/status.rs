@@ -0,0 +1,258 @@
+use core::{fmt, sync::atomic::Ordering};
+
+use log::trace;
+
+use super::{EMmcHost, cmd::EMmcCommand, constant::*};
+use crate::err::SdError;
+
+/// Card state machine states, as reported in CURRENT_STATE of R1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardState {
+    Idle,
+    Ready,
+    Ident,
+    Stby,
+    Tran,
+    Data,
+    Rcv,
+    Prg,
+    Dis,
+    Btst,
+    Slp,
+    Reserved(u8),
+}
+
+impl From<u8> for CardState {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => CardState::Idle,
+            1 => CardState::Ready,
+            2 => CardState::Ident,
+            3 => CardState::Stby,
+            4 => CardState::Tran,
+            5 => CardState::Data,
+            6 => CardState::Rcv,
+            7 => CardState::Prg,
+            8 => CardState::Dis,
+            9 => CardState::Btst,
+            10 => CardState::Slp,
+            other => CardState::Reserved(other),
+        }
+    }
+}
+
+impl From<CardState> for u8 {
+    fn from(state: CardState) -> Self {
+        match state {
+            CardState::Idle => 0,
+            CardState::Ready => 1,
+            CardState::Ident => 2,
+            CardState::Stby => 3,
+            CardState::Tran => 4,
+            CardState::Data => 5,
+            CardState::Rcv => 6,
+            CardState::Prg => 7,
+            CardState::Dis => 8,
+            CardState::Btst => 9,
+            CardState::Slp => 10,
+            CardState::Reserved(other) => other,
+        }
+    }
+}
+
+macro_rules! card_status_bits {
+    ($($name:ident => $mask:ident, $desc:literal);* $(;)?) => {
+        impl CardStatus {
+            $(
+                pub fn $name(&self) -> bool {
+                    self.0 & $mask != 0
+                }
+            )*
+        }
+
+        const CARD_STATUS_BITS: &[(u32, &str)] = &[$(($mask, $desc)),*];
+    };
+}
+
+/// Decoded R1 card status (also returned by CMD13)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CardStatus(pub u32);
+
+card_status_bits! {
+    out_of_range => MMC_STATUS_OUT_OF_RANGE, "OUT_OF_RANGE";
+    address_misalign => MMC_STATUS_ADDRESS_MISALIGN, "ADDRESS_MISALIGN";
+    block_len_error => MMC_STATUS_BLOCK_LEN_ERROR, "BLOCK_LEN_ERROR";
+    erase_seq_error => MMC_STATUS_ERASE_SEQ_ERROR, "ERASE_SEQ_ERROR";
+    erase_param => MMC_STATUS_ERASE_PARAM, "ERASE_PARAM";
+    wp_violation => MMC_STATUS_WP_VIOLATION, "WP_VIOLATION";
+    card_is_locked => MMC_STATUS_CARD_IS_LOCKED, "CARD_IS_LOCKED";
+    lock_unlock_failed => MMC_STATUS_LOCK_UNLOCK_FAILED, "LOCK_UNLOCK_FAILED";
+    com_crc_error => MMC_STATUS_COM_CRC_ERROR, "COM_CRC_ERROR";
+    illegal_command => MMC_STATUS_ILLEGAL_COMMAND, "ILLEGAL_COMMAND";
+    card_ecc_failed => MMC_STATUS_CARD_ECC_FAILED, "CARD_ECC_FAILED";
+    cc_error => MMC_STATUS_CC_ERROR, "CC_ERROR";
+    error => MMC_STATUS_ERROR, "ERROR";
+    cid_csd_overwrite => MMC_STATUS_CID_CSD_OVERWRITE, "CID_CSD_OVERWRITE";
+    wp_erase_skip => MMC_STATUS_WP_ERASE_SKIP, "WP_ERASE_SKIP";
+    card_ecc_disabled => MMC_STATUS_CARD_ECC_DISABLED, "CARD_ECC_DISABLED";
+    erase_reset => MMC_STATUS_ERASE_RESET, "ERASE_RESET";
+    ready_for_data => MMC_STATUS_RDY_FOR_DATA, "READY_FOR_DATA";
+    switch_error => MMC_STATUS_SWITCH_ERROR, "SWITCH_ERROR";
+    exception_event => MMC_STATUS_EXCEPTION_EVENT, "EXCEPTION_EVENT";
+    app_cmd => MMC_STATUS_APP_CMD, "APP_CMD";
+}
+
+impl CardStatus {
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+
+    pub fn state(&self) -> CardState {
+        CardState::from(((self.0 & MMC_STATUS_CURR_STATE) >> 9) as u8)
+    }
+
+    /// Error bits set in this status
+    pub fn error_bits(&self) -> u32 {
+        self.0 & MMC_STATUS_ERROR_BITS
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.error_bits() != 0
+    }
+
+    /// Name of the most significant error bit, if any
+    pub fn description(&self) -> &'static str {
+        CARD_STATUS_BITS
+            .iter()
+            .find(|(mask, _)| self.error_bits() & mask != 0)
+            .map(|(_, desc)| *desc)
+            .unwrap_or("no error")
+    }
+}
+
+impl From<u32> for CardStatus {
+    fn from(value: u32) -> Self {
+        CardStatus(value)
+    }
+}
+
+impl fmt::Debug for CardStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CardStatus({:#010x}, {:?}", self.0, self.state())?;
+        for (mask, desc) in CARD_STATUS_BITS {
+            if self.0 & mask != 0 {
+                write!(f, " {}", desc)?;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for CardStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x} ({:?}", self.0, self.state())?;
+        for (mask, desc) in CARD_STATUS_BITS {
+            if self.error_bits() & mask != 0 {
+                write!(f, ", {}", desc)?;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+impl EMmcHost {
+    /// Last known state of the card state machine, tracked on every command
+    pub fn card_state(&self) -> Option<CardState> {
+        let card = self.card.as_ref()?;
+        Some(CardState::from(card.card_state.load(Ordering::SeqCst)))
+    }
+
+    pub(crate) fn mmc_set_card_state(&self, state: CardState) {
+        if let Some(card) = self.card.as_ref() {
+            card.card_state.store(state.into(), Ordering::SeqCst);
+        }
+    }
+
+    fn mmc_resp_is_r1(&self, cmd: &EMmcCommand) -> bool {
+        if cmd.resp_type != MMC_RSP_R1 && cmd.resp_type != MMC_RSP_R1B {
+            return false;
+        }
+
+        // SD CMD3 (R6) and CMD8 (R7) share the R1 response flags
+        !(self.mmc_card_is_sd()
+            && (cmd.opcode == SD_SEND_RELATIVE_ADDR || cmd.opcode == SD_SEND_IF_COND))
+    }
+
+    fn mmc_card_is_sd(&self) -> bool {
+        self.card
+            .as_ref()
+            .is_some_and(|card| card.card_type.is_sd())
+    }
+
+    /// Update the tracked card state after a completed command
+    pub(crate) fn mmc_track_card_state(&self, cmd: &EMmcCommand, response: u32) {
+        let previous = match self.card_state() {
+            Some(state) => state,
+            None => return,
+        };
+
+        let is_sd = self.mmc_card_is_sd();
+        let state = match cmd.opcode {
+            MMC_GO_IDLE_STATE => CardState::Idle,
+            MMC_SEND_OP_COND if response & OCR_BUSY != 0 => CardState::Ready,
+            MMC_SEND_OP_COND => CardState::Idle,
+            MMC_ALL_SEND_CID => CardState::Ident,
+            MMC_SET_RELATIVE_ADDR => CardState::Stby,
+            // Deselecting a programming card disconnects it, selecting a
+            // disconnected one resumes programming
+            MMC_SELECT_CARD if cmd.arg >> 16 == 0 && previous == CardState::Prg => CardState::Dis,
+            MMC_SELECT_CARD if cmd.arg >> 16 == 0 => CardState::Stby,
+            MMC_SELECT_CARD if previous == CardState::Dis => CardState::Prg,
+            MMC_SELECT_CARD => CardState::Tran,
+            MMC_STOP_TRANSMISSION if matches!(previous, CardState::Rcv | CardState::Prg) => {
+                CardState::Prg
+            }
+            MMC_STOP_TRANSMISSION => CardState::Tran,
+            // Single block transfers have completed by the time send_command
+            // returns, CMD8 is SEND_IF_COND rather than SEND_EXT_CSD on SD
+            MMC_SEND_EXT_CSD if !is_sd => CardState::Tran,
+            MMC_READ_SINGLE_BLOCK | MMC_SEND_TUNING_BLOCK_HS200 => CardState::Tran,
+            MMC_READ_MULTIPLE_BLOCK => CardState::Data,
+            MMC_WRITE_BLOCK => CardState::Prg,
+            MMC_WRITE_MULTIPLE_BLOCK => CardState::Rcv,
+            _ if cmd.resp_type == MMC_RSP_R1B => CardState::Prg,
+            _ if self.mmc_resp_is_r1(cmd) => CardStatus(response).state(),
+            _ => previous,
+        };
+
+        if state != previous {
+            trace!(
+                "Card state {:?} -> {:?} (CMD{})",
+                previous, state, cmd.opcode
+            );
+        }
+        self.mmc_set_card_state(state);
+    }
+
+    /// Turn error bits reported in an R1 response into `SdError::CardError`
+    pub(crate) fn mmc_check_r1(&self, cmd: &EMmcCommand, response: u32) -> Result<(), SdError> {
+        if !self.mmc_resp_is_r1(cmd) {
+            return Ok(());
+        }
+
+        let mut status = CardStatus(response);
+        // Reading up to the last block reports OUT_OF_RANGE on the stop command
+        if cmd.opcode == MMC_STOP_TRANSMISSION {
+            status.0 &= !MMC_STATUS_OUT_OF_RANGE;
+        }
+
+        if status.is_error() {
+            return Err(SdError::CardError {
+                opcode: cmd.opcode,
+                status,
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use core::time::Duration;
+
+use super::EMmcHost;
+use crate::{Deadline, Kernel, err::SdError};
+
+/// Wait for CMD/DAT inhibit to clear before issuing a command
+pub const EMMC_INHIBIT_TIMEOUT: Duration = Duration::from_millis(500);
+/// Wait for the command complete interrupt
+pub const EMMC_CMD_TIMEOUT: Duration = Duration::from_millis(10);
+/// Command complete wait for CMD0/CMD1, which some cards answer slowly
+pub const EMMC_CMD_MAX_TIMEOUT: Duration = Duration::from_millis(50);
+/// Wait for a software reset of the whole controller
+pub const EMMC_RESET_TIMEOUT: Duration = Duration::from_millis(20);
+/// Wait for a software reset of the CMD or DAT circuit
+pub const EMMC_LINE_RESET_TIMEOUT: Duration = Duration::from_millis(100);
+/// Wait for the internal clock to become stable
+pub const EMMC_CLOCK_STABLE_TIMEOUT: Duration = Duration::from_millis(20);
+/// Wait for the DWCMSHC DLL to lock
+pub const EMMC_DLL_LOCK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Shortest wait for a data transfer, regardless of the card access time
+pub const EMMC_DATA_TIMEOUT: Duration = Duration::from_millis(100);
+/// Busy wait for operations without a card-specified time
+pub const EMMC_BUSY_TIMEOUT: Duration = Duration::from_millis(1000);
+/// CMD6 busy wait for cards that do not report GENERIC_CMD6_TIME
+pub const EMMC_CMD6_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Interval between CMD13/DAT0 polls while the card is busy
+pub(crate) const EMMC_BUSY_POLL_US: u64 = 1000;
+
+impl EMmcHost {
+    /// Use `kernel` for delays and timeouts of this host instead of the
+    /// one registered with `set_kernel`
+    pub fn with_kernel(mut self, kernel: &'static dyn Kernel) -> Self {
+        self.kernel = Some(kernel);
+        self
+    }
+
+    /// Kernel of this host, `SdError::NoKernel` when neither `with_kernel`
+    /// nor `set_kernel` provided one
+    pub(crate) fn kernel(&self) -> Result<&'static dyn Kernel, SdError> {
+        self.kernel.or_else(crate::kernel).ok_or(SdError::NoKernel)
+    }
+
+    /// Spin instead of sleeping or yielding, for hosts only used under a
+    /// spinlock with interrupts disabled
+    pub(crate) fn set_atomic(&mut self, atomic: bool) {
+        self.atomic = atomic;
+    }
+
+    pub(crate) fn delay_us(&self, us: u64) -> Result<(), SdError> {
+        if self.atomic {
+            let deadline = self.deadline(Duration::from_micros(us))?;
+            while !deadline.expired() {
+                core::hint::spin_loop();
+            }
+        } else {
+            self.kernel()?.sleep(us);
+        }
+        Ok(())
+    }
+
+    /// Let other work run between two polls of the hardware
+    pub(crate) fn mmc_relax(&self) -> Result<(), SdError> {
+        if self.atomic {
+            core::hint::spin_loop();
+        } else {
+            self.kernel()?.yield_now();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn deadline(&self, timeout: Duration) -> Result<Deadline, SdError> {
+        Ok(Deadline::after(self.kernel()?, timeout))
+    }
+
+    /// Poll `done` until it returns true, yielding in between unless atomic.
+    ///
+    /// Fails with `SdError::Timeout` when `timeout` passes first. Errors
+    /// returned by `done` end the wait immediately.
+    pub(crate) fn wait_for(
+        &self,
+        timeout: Duration,
+        mut done: impl FnMut() -> Result<bool, SdError>,
+    ) -> Result<(), SdError> {
+        let deadline = self.deadline(timeout)?;
+        loop {
+            // Check once more after expiry, we may have been preempted
+            let expired = deadline.expired();
+            if done()? {
+                return Ok(());
+            }
+            if expired {
+                return Err(SdError::Timeout);
+            }
+            self.mmc_relax()?;
+        }
+    }
+}
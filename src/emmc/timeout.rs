@@ -0,0 +1,146 @@
+use core::time::Duration;
+
+use super::{
+    EMMC_BUSY_TIMEOUT, EMMC_CMD_MAX_TIMEOUT, EMMC_CMD_TIMEOUT, EMMC_CMD6_TIMEOUT,
+    EMMC_DATA_TIMEOUT, EMMC_INHIBIT_TIMEOUT, EMmcHost, constant::*, info::Csd,
+};
+
+/// The data timeout counter runs for 2^(13 + TIMEOUT_CONTROL) TMCLK cycles
+const EMMC_TIMEOUT_CONTROL_BASE_SHIFT: u32 = 13;
+/// Largest valid TIMEOUT_CONTROL value
+pub(crate) const EMMC_TIMEOUT_CONTROL_MAX: u8 = 0xE;
+
+/// Timeouts applied by a host.
+///
+/// Times reported by the card (CSD access time, GENERIC_CMD6_TIME,
+/// PARTITION_SWITCH_TIME) take precedence, these values are used when the
+/// card gives none and as lower bounds for data transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutPolicy {
+    /// Wait for CMD/DAT inhibit to clear before issuing a command
+    pub inhibit: Duration,
+    /// Wait for the command complete interrupt
+    pub cmd: Duration,
+    /// Command complete wait for CMD0/CMD1
+    pub cmd_slow: Duration,
+    /// Shortest wait for a data transfer, also used when the CSD gives no
+    /// access time
+    pub data_min: Duration,
+    /// Busy wait for operations without a card-specified time
+    pub busy: Duration,
+    /// CMD6 busy wait when GENERIC_CMD6_TIME is not set
+    pub cmd6: Duration,
+}
+
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        Self {
+            inhibit: EMMC_INHIBIT_TIMEOUT,
+            cmd: EMMC_CMD_TIMEOUT,
+            cmd_slow: EMMC_CMD_MAX_TIMEOUT,
+            data_min: EMMC_DATA_TIMEOUT,
+            busy: EMMC_BUSY_TIMEOUT,
+            cmd6: EMMC_CMD6_TIMEOUT,
+        }
+    }
+}
+
+impl EMmcHost {
+    /// Use `policy` for the timeouts of this host
+    pub fn with_timeout_policy(mut self, policy: TimeoutPolicy) -> Self {
+        self.timeouts = policy;
+        self
+    }
+
+    pub fn set_timeout_policy(&mut self, policy: TimeoutPolicy) {
+        self.timeouts = policy;
+    }
+
+    pub fn timeout_policy(&self) -> &TimeoutPolicy {
+        &self.timeouts
+    }
+
+    /// Longest time the card may take to read or program a single block,
+    /// from the CSD access time at the current bus clock.
+    ///
+    /// `None` when no card is attached or the CSD reports no access time.
+    pub fn block_timeout(&self, write: bool) -> Option<Duration> {
+        let card = self.card.as_ref()?;
+        let csd = Csd::decode(&card.csd, card.card_type);
+
+        // Read access is specified as 1/10 (eMMC) or 1/100 (SD) of the timeout
+        let mult: u64 = if card.card_type.is_sd() { 100 } else { 10 };
+        let mut ns = csd.access_time_ns() as u64 * mult;
+        let mut clks = csd.access_time_clks() as u64 * mult;
+        if write {
+            ns <<= csd.r2w_factor;
+            clks <<= csd.r2w_factor;
+        }
+
+        let clock = if self.clock != 0 {
+            self.clock
+        } else {
+            card.clock
+        };
+        if clock != 0 {
+            ns += clks * 1_000_000_000 / clock as u64;
+        }
+
+        (ns != 0).then(|| Duration::from_nanos(ns))
+    }
+
+    /// Software wait for a whole transfer of `blocks` blocks
+    pub(crate) fn mmc_data_timeout(&self, write: bool, blocks: u32) -> Duration {
+        match self.block_timeout(write) {
+            Some(timeout) => (timeout * blocks.max(1)).max(self.timeouts.data_min),
+            None if write => self.timeouts.busy.max(self.timeouts.data_min),
+            None => self.timeouts.data_min,
+        }
+    }
+
+    /// TIMEOUT_CONTROL value covering the per-block access time of the card
+    pub(crate) fn mmc_timeout_control(&self, write: bool) -> u8 {
+        let tmclk_khz = self.timeout_clk_khz();
+        let timeout = match self.block_timeout(write) {
+            Some(timeout) if tmclk_khz != 0 => timeout,
+            _ => return EMMC_TIMEOUT_CONTROL_MAX,
+        };
+
+        let cycles = timeout.as_nanos() as u64 * tmclk_khz / 1_000_000;
+        let mut count = 0;
+        while count < EMMC_TIMEOUT_CONTROL_MAX
+            && (1u64 << (EMMC_TIMEOUT_CONTROL_BASE_SHIFT + count as u32)) < cycles
+        {
+            count += 1;
+        }
+        count
+    }
+
+    /// Busy wait for a CMD6 writing EXT_CSD byte `index`
+    pub(crate) fn mmc_switch_timeout(&self, index: u32) -> Duration {
+        let (generic, part) = self.card.as_ref().map_or((0, 0), |card| {
+            (card.generic_cmd6_time, card.part_switch_time)
+        });
+
+        let ms = if index == EXT_CSD_PART_CONF && part != 0 {
+            part
+        } else {
+            generic
+        };
+        if ms == 0 {
+            self.timeouts.cmd6
+        } else {
+            Duration::from_millis(ms as u64)
+        }
+    }
+
+    /// Data timeout clock (TMCLK) frequency from the capabilities, 0 if unknown
+    fn timeout_clk_khz(&self) -> u64 {
+        let freq = ((self.caps & EMMC_TIMEOUT_CLK_MASK) >> EMMC_TIMEOUT_CLK_SHIFT) as u64;
+        if self.caps & EMMC_TIMEOUT_CLK_UNIT != 0 {
+            freq * 1000
+        } else {
+            freq
+        }
+    }
+}
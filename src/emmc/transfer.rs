@@ -0,0 +1,40 @@
+use super::EMmcHost;
+
+/// How block transfers move data between memory and the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferMode {
+    /// The CPU copies every word through the buffer data port
+    Pio,
+    /// SDMA, buffers that are not DMA capable are bounced through a `DVec`
+    Dma,
+}
+
+impl Default for TransferMode {
+    /// DMA with the `dma` feature, PIO otherwise
+    fn default() -> Self {
+        if cfg!(feature = "dma") {
+            TransferMode::Dma
+        } else {
+            TransferMode::Pio
+        }
+    }
+}
+
+impl EMmcHost {
+    /// Move block data with `mode`, see `set_transfer_mode`
+    pub fn with_transfer_mode(mut self, mode: TransferMode) -> Self {
+        self.transfer_mode = mode;
+        self
+    }
+
+    /// Mode used by `read_blocks` and `write_blocks`. Small internal
+    /// transfers such as EXT_CSD always use PIO, `read_blocks_dma` and
+    /// `write_blocks_dma` always use DMA.
+    pub fn set_transfer_mode(&mut self, mode: TransferMode) {
+        self.transfer_mode = mode;
+    }
+
+    pub fn transfer_mode(&self) -> TransferMode {
+        self.transfer_mode
+    }
+}
@@ -0,0 +1,127 @@
+use alloc::boxed::Box;
+
+use super::{EMmcHost, cmd::EMmcCommand, constant::*};
+use crate::err::SdError;
+
+/// EXT_CSD bytes holding volatile bus configuration only. They are still
+/// written in read-only mode so the card can be initialized and read.
+const EXT_CSD_VOLATILE: [u32; 4] = [
+    EXT_CSD_BUS_WIDTH,
+    EXT_CSD_HS_TIMING,
+    EXT_CSD_ERASE_GROUP_DEF,
+    EXT_CSD_HPI_MGMT,
+];
+
+/// Board GPIO write-protect switch, used instead of the controller WP pin
+/// when present
+pub trait WriteProtect: Send {
+    /// The switch is in the protected position, polarity already applied
+    fn is_write_protected(&self) -> bool;
+}
+
+impl core::fmt::Debug for dyn WriteProtect {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "WriteProtect")
+    }
+}
+
+impl EMmcHost {
+    /// Read the write-protect switch through `wp` instead of the controller
+    pub fn with_write_protect(mut self, wp: Box<dyn WriteProtect>) -> Self {
+        self.write_protect = Some(wp);
+        self
+    }
+
+    /// Software read-only mode, see `set_read_only`
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Reject every command that could modify the card: writes, erases,
+    /// write protection, lock/unlock and EXT_CSD changes other than bus
+    /// configuration. Reads keep working.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Reject writes and erases while hardware partition `part` is selected,
+    /// `part` being a PARTITION_ACCESS value
+    pub fn set_partition_read_only(&mut self, part: u8, read_only: bool) -> Result<(), SdError> {
+        if part as u32 > PART_ACCESS_MASK {
+            return Err(SdError::InvalidArgument);
+        }
+
+        if read_only {
+            self.read_only_parts |= 1 << part;
+        } else {
+            self.read_only_parts &= !(1 << part);
+        }
+        Ok(())
+    }
+
+    /// State of the write-protect switch
+    pub fn is_write_protected(&self) -> bool {
+        match &self.write_protect {
+            Some(wp) => wp.is_write_protected(),
+            None => self.read_reg(EMMC_PRESENT_STATE) & EMMC_WRITE_PROTECT != 0,
+        }
+    }
+
+    /// Fail with `SdError::WriteProtected` unless the current partition may be
+    /// modified
+    pub(crate) fn mmc_check_writable(&self) -> Result<(), SdError> {
+        if self.mmc_sw_read_only() || self.is_write_protected() {
+            return Err(SdError::WriteProtected);
+        }
+        Ok(())
+    }
+
+    /// Last line of defence in `send_command` against modifying a read-only card
+    pub(crate) fn mmc_check_cmd_allowed(&self, cmd: &EMmcCommand) -> Result<(), SdError> {
+        let blocked = match cmd.opcode {
+            MMC_WRITE_BLOCK
+            | MMC_WRITE_MULTIPLE_BLOCK
+            | MMC_EARSE_GROUP_START
+            | MMC_EARSE_GROUP_END
+            | MMC_ERASE
+            | MMC_EXECUTE_WRITE_TASK => self.mmc_sw_read_only(),
+            MMC_PROGRAM_CID | MMC_PROGRAM_CSD | MMC_SET_WRITE_PROT | MMC_CLR_WRITE_PROT
+            | MMC_LOCK_UNLOCK | MMC_SET_TIME => self.read_only,
+            // GEN_CMD writes when bit 0 of the argument is clear
+            MMC_GEN_CMD => self.read_only && cmd.arg & 0x1 == 0,
+            MMC_SWITCH => self.read_only && !self.mmc_switch_is_volatile(cmd.arg),
+            _ => false,
+        };
+
+        if blocked {
+            return Err(SdError::WriteProtected);
+        }
+        Ok(())
+    }
+
+    fn mmc_sw_read_only(&self) -> bool {
+        let part = match self.part_config() {
+            None | Some(MMCPART_NOAVAILABLE) => 0,
+            Some(part_config) => part_config as u32 & PART_ACCESS_MASK,
+        };
+        self.read_only || self.read_only_parts & (1 << part) != 0
+    }
+
+    /// The CMD6 argument only touches volatile configuration
+    fn mmc_switch_is_volatile(&self, arg: u32) -> bool {
+        let mode = (arg >> 24) & 0x3;
+        let index = (arg >> 16) & 0xFF;
+        let value = ((arg >> 8) & 0xFF) as u8;
+
+        if mode == MMC_SWITCH_MODE_CMD_SET || EXT_CSD_VOLATILE.contains(&index) {
+            return true;
+        }
+
+        // Selecting a partition is fine, the boot configuration is not
+        let part_config = self.part_config().unwrap_or(0);
+        index == EXT_CSD_PART_CONF
+            && mode == MMC_SWITCH_MODE_WRITE_BYTE
+            && (value ^ part_config) & !(PART_ACCESS_MASK as u8) == 0
+    }
+}
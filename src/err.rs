@@ -1,8 +1,73 @@
 // ===== Types and Structures =====
 
+use alloc::boxed::Box;
 use core::fmt;
 
-#[derive(Debug)]
+use crate::emmc::{
+    CardStatus,
+    clock::ClkError,
+    constant::{MMC_STATUS_COM_CRC_ERROR, MMC_STATUS_ERROR},
+};
+
+/// Broad cause of an error, telling callers how to react to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Bus or signal integrity problem, retrying after recovery may succeed
+    Transient,
+    /// The card rejected the request or is not usable
+    Card,
+    /// Controller, clock or power failure
+    Host,
+    /// The request itself is invalid, retrying cannot help
+    Programming,
+}
+
+/// A failed command along with the controller state seen when it failed
+#[derive(Debug, Clone)]
+pub struct CommandError {
+    /// What went wrong, never `SdError::Command` itself
+    pub kind: SdError,
+    pub opcode: u8,
+    pub arg: u32,
+    /// Blocks requested, 0 for commands without data
+    pub blocks: u16,
+    /// Bus timing in use, one of the `MMC_TIMING_*` values
+    pub timing: u32,
+    /// Raw EMMC_NORMAL_INT_STAT
+    pub normal_status: u16,
+    /// Raw EMMC_ERROR_INT_STAT
+    pub error_status: u16,
+    /// Raw EMMC_AUTO_CMD_STAT
+    pub auto_cmd_status: u16,
+    /// Raw EMMC_ADMA_ERR_STAT
+    pub adma_status: u8,
+    /// R1 card status, when the card answered
+    pub r1: Option<CardStatus>,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (CMD{} arg={:#010x} blocks={} timing={} int={:#06x} err={:#06x} auto_cmd={:#06x} adma={:#04x}",
+            self.kind,
+            self.opcode,
+            self.arg,
+            self.blocks,
+            self.timing,
+            self.normal_status,
+            self.error_status,
+            self.auto_cmd_status,
+            self.adma_status
+        )?;
+        if let Some(r1) = &self.r1 {
+            write!(f, " r1={}", r1)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum SdError {
     Timeout,
     Crc,
@@ -30,7 +95,95 @@ pub enum SdError {
     BufferOverflow,
     MemoryError,
     BusWidth,
-    CardError(u32, &'static str), // 包含错误状态和描述
+    Cancelled,
+    OutOfRange,
+    InvalidPartitionTable,
+    PartitionNotFound,
+    WriteProtected,
+    NoKernel,
+    /// A failed recovery left the card unusable until `EMmcHost::recover`,
+    /// with the error that started it
+    NeedsReinit(Box<SdError>),
+    CardError {
+        opcode: u8,
+        status: CardStatus,
+    }, // 卡在R1中报告的错误
+    FfuError(u8, &'static str),
+    ClockError(ClkError),
+    /// An error raised by a command, with its context
+    Command(Box<CommandError>),
+}
+
+impl SdError {
+    /// The error without command context
+    pub fn kind(&self) -> &SdError {
+        match self {
+            SdError::Command(e) => e.kind.kind(),
+            e => e,
+        }
+    }
+
+    /// Command and controller state of the failure, when known
+    pub fn context(&self) -> Option<&CommandError> {
+        match self {
+            SdError::Command(e) => Some(e),
+            SdError::NeedsReinit(e) => e.context(),
+            _ => None,
+        }
+    }
+
+    pub fn class(&self) -> ErrorClass {
+        match self.kind() {
+            SdError::Timeout
+            | SdError::Crc
+            | SdError::EndBit
+            | SdError::Index
+            | SdError::DataTimeout
+            | SdError::DataCrc
+            | SdError::DataEndBit
+            | SdError::DataError
+            | SdError::TransferError
+            | SdError::Acmd12Error => ErrorClass::Transient,
+            // Errors the card saw on the bus go away like the bus errors causing them
+            SdError::CardError { status, .. }
+                if status.0 & (MMC_STATUS_COM_CRC_ERROR | MMC_STATUS_ERROR) != 0 =>
+            {
+                ErrorClass::Transient
+            }
+            SdError::CardError { .. }
+            | SdError::InvalidResponse
+            | SdError::InvalidResponseType
+            | SdError::NoCard
+            | SdError::UnsupportedCard
+            | SdError::BadMessage
+            | SdError::BusWidth
+            | SdError::InvalidPartitionTable
+            | SdError::PartitionNotFound
+            | SdError::WriteProtected
+            | SdError::NeedsReinit(_)
+            | SdError::FfuError(..) => ErrorClass::Card,
+            SdError::BusPower
+            | SdError::AdmaError
+            | SdError::IoError
+            | SdError::CommandError
+            | SdError::CurrentLimit
+            | SdError::TuningFailed
+            | SdError::VoltageSwitchFailed
+            | SdError::MemoryError
+            | SdError::ClockError(_) => ErrorClass::Host,
+            SdError::InvalidArgument
+            | SdError::BufferOverflow
+            | SdError::OutOfRange
+            | SdError::Cancelled
+            | SdError::NoKernel => ErrorClass::Programming,
+            SdError::Command(_) => unreachable!(),
+        }
+    }
+
+    /// Retrying after recovering the card may succeed
+    pub fn is_retryable(&self) -> bool {
+        self.class() == ErrorClass::Transient
+    }
 }
 
 impl fmt::Display for SdError {
@@ -62,7 +215,25 @@ impl fmt::Display for SdError {
             SdError::BufferOverflow => write!(f, "Buffer overflow"),
             SdError::MemoryError => write!(f, "Memory error"),
             SdError::BusWidth => write!(f, "Bus width error"),
-            SdError::CardError(status, desc) => write!(f, "Card error: 0x{:X} ({})", status, desc),
+            SdError::Cancelled => write!(f, "Operation cancelled"),
+            SdError::OutOfRange => write!(f, "Address out of range"),
+            SdError::InvalidPartitionTable => write!(f, "Invalid partition table"),
+            SdError::PartitionNotFound => write!(f, "Partition not found"),
+            SdError::WriteProtected => write!(f, "Write protected"),
+            SdError::NoKernel => write!(f, "No kernel, call with_kernel or set_kernel"),
+            SdError::NeedsReinit(e) => write!(f, "Card needs re-initialization after: {}", e),
+            SdError::CardError { opcode, status } => {
+                write!(f, "Card error on CMD{}: {}", opcode, status)
+            }
+            SdError::FfuError(status, desc) => write!(f, "FFU error: 0x{:X} ({})", status, desc),
+            SdError::ClockError(e) => write!(f, "Clock error: {:?}", e),
+            SdError::Command(e) => write!(f, "{}", e),
         }
     }
 }
+
+impl From<ClkError> for SdError {
+    fn from(e: ClkError) -> Self {
+        SdError::ClockError(e)
+    }
+}
@@ -0,0 +1,91 @@
+// ===== embedded-sdmmc Integration =====
+
+use alloc::vec;
+
+use embedded_sdmmc::{Block, BlockCount, BlockIdx, TimeSource, Timestamp};
+
+use crate::{BLOCK_SIZE, Kernel, block::BlockDevice, emmc::EMmcHost, err::SdError, kernel};
+
+/// Lets `embedded_sdmmc::VolumeManager` open FAT16/FAT32 volumes on the card
+impl embedded_sdmmc::BlockDevice for EMmcHost {
+    type Error = SdError;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), SdError> {
+        let mut buf = vec![0u8; blocks.len() * BLOCK_SIZE];
+        BlockDevice::read(self, start_block_idx.0 as u64, &mut buf)?;
+
+        for (block, data) in blocks.iter_mut().zip(buf.chunks_exact(BLOCK_SIZE)) {
+            block.contents.copy_from_slice(data);
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), SdError> {
+        let mut buf = vec![0u8; blocks.len() * BLOCK_SIZE];
+        for (block, data) in blocks.iter().zip(buf.chunks_exact_mut(BLOCK_SIZE)) {
+            data.copy_from_slice(&block.contents);
+        }
+
+        BlockDevice::write(self, start_block_idx.0 as u64, &buf)
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, SdError> {
+        let blocks = BlockDevice::num_blocks(self);
+        if blocks == 0 {
+            return Err(SdError::NoCard);
+        }
+
+        u32::try_from(blocks)
+            .map(BlockCount)
+            .map_err(|_| SdError::OutOfRange)
+    }
+}
+
+/// File timestamps from `Kernel::wall_clock`, falling back to the FAT epoch
+/// (1980-01-01 00:00:00) when the platform has no real-time clock.
+///
+/// `EMmcHost::time_source` reads the clock of the host's kernel, the
+/// default one that of the kernel registered with `set_kernel`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KernelTimeSource {
+    kernel: Option<&'static dyn Kernel>,
+}
+
+impl EMmcHost {
+    /// Timestamps for files written through `embedded_sdmmc` on this host
+    pub fn time_source(&self) -> KernelTimeSource {
+        KernelTimeSource {
+            kernel: self.kernel().ok(),
+        }
+    }
+}
+
+impl TimeSource for KernelTimeSource {
+    fn get_timestamp(&self) -> Timestamp {
+        const FAT_EPOCH: Timestamp = Timestamp {
+            year_since_1970: 10,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        };
+
+        self.kernel
+            .or_else(kernel)
+            .and_then(|kernel| kernel.wall_clock())
+            .and_then(|now| {
+                Timestamp::from_calendar(
+                    now.year,
+                    now.month,
+                    now.day,
+                    now.hours,
+                    now.minutes,
+                    now.seconds,
+                )
+                .ok()
+            })
+            .unwrap_or(FAT_EPOCH)
+    }
+}
@@ -2,10 +2,21 @@
 #![no_main]
 #![feature(alloc_error_handler)]
 
+extern crate alloc;
+
+pub mod block;
+pub mod cache;
 pub mod emmc;
 pub mod err;
+#[cfg(feature = "embedded-sdmmc")]
+pub mod fat;
+pub mod partition;
+pub mod queue;
+
+use core::time::Duration;
 
 use log::warn;
+use spin::Once;
 
 pub const BLOCK_SIZE: usize = 512;
 
@@ -31,26 +42,81 @@ pub unsafe fn dump_memory_region(addr: usize, size: usize) {
     warn!("");
 }
 
-pub trait Kernel {
-    fn sleep(us: u64);
+/// Calendar date and time, as provided by the platform's real-time clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallClock {
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
 }
 
-pub(crate) fn delay_us(us: u64) {
-    unsafe extern "Rust" {
-        fn delay_us(us: u64);
+/// Platform services used by the driver: a monotonic clock and delays.
+///
+/// Every host can be given its own implementation with
+/// `EMmcHost::with_kernel`, hosts without one use the kernel registered
+/// with `set_kernel`.
+pub trait Kernel: Sync {
+    /// Monotonic time since an arbitrary epoch, never goes backwards
+    fn now(&self) -> Duration;
+
+    /// Wait at least `us` microseconds, spins on `now()` by default
+    fn sleep(&self, us: u64) {
+        let deadline = self.now() + Duration::from_micros(us);
+        while self.now() < deadline {
+            core::hint::spin_loop();
+        }
     }
 
-    unsafe {
-        delay_us(us);
+    /// Let other work run while the driver polls the hardware.
+    ///
+    /// Neither this nor `sleep` is called for hosts owned by
+    /// `SharedEMmcHost`, those spin on `now()` with interrupts disabled.
+    fn yield_now(&self) {}
+
+    /// Current calendar time, `None` if the platform has no real-time clock
+    fn wall_clock(&self) -> Option<WallClock> {
+        None
     }
 }
 
-#[macro_export]
-macro_rules! set_impl {
-    ($t: ty) => {
-        #[unsafe(no_mangle)]
-        unsafe fn delay_us(us: u64) {
-            <$t as $crate::Kernel>::sleep(us)
+impl core::fmt::Debug for dyn Kernel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Kernel")
+    }
+}
+
+static KERNEL: Once<&'static dyn Kernel> = Once::new();
+
+/// Register the kernel used by hosts that were not given their own.
+/// Only the first call has an effect.
+pub fn set_kernel(kernel: &'static dyn Kernel) {
+    KERNEL.call_once(|| kernel);
+}
+
+pub(crate) fn kernel() -> Option<&'static dyn Kernel> {
+    KERNEL.get().copied()
+}
+
+/// Point in time after which a wait is abandoned
+pub(crate) struct Deadline {
+    kernel: &'static dyn Kernel,
+    end: Duration,
+}
+
+impl Deadline {
+    pub(crate) fn after(kernel: &'static dyn Kernel, timeout: Duration) -> Self {
+        Self {
+            kernel,
+            end: kernel.now().saturating_add(timeout),
         }
-    };
+    }
+
+    pub(crate) fn expired(&self) -> bool {
+        self.kernel.now() >= self.end
+    }
 }
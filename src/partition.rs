@@ -0,0 +1,386 @@
+// ===== Partition Tables =====
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+use log::{debug, warn};
+
+use crate::{
+    block::{BlockDevice, Partition},
+    err::SdError,
+};
+
+const MBR_SIGNATURE: u16 = 0xAA55;
+const MBR_ENTRY_OFFSET: usize = 446;
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+/* Extended partition types whose content is a chain of EBRs */
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/* Upper bound on logical partitions, guards against EBR loops */
+const MBR_MAX_LOGICAL: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_ENTRY_MIN_SIZE: usize = 128;
+const GPT_MAX_ENTRIES: usize = 1024;
+
+/// Partition table layout found on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    Mbr,
+    Gpt,
+}
+
+/// GPT GUID, stored in its on-disk mixed-endian byte order
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|&b| b == 0)
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        for byte in &b[10..] {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Guid({})", self)
+    }
+}
+
+/// Partition type as recorded in the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    Mbr(u8),
+    Gpt(Guid),
+}
+
+/// One entry of an MBR or GPT partition table
+#[derive(Debug, Clone)]
+pub struct PartitionEntry {
+    /// 1-based partition number; MBR logical partitions start at 5
+    pub index: usize,
+    /// GPT partition name, empty for MBR partitions
+    pub name: String,
+    pub part_type: PartitionType,
+    /// GPT unique partition GUID
+    pub guid: Option<Guid>,
+    pub start_lba: u64,
+    pub num_blocks: u64,
+    /// GPT attribute bits, or the MBR boot indicator in bit 2 (legacy BIOS bootable)
+    pub attributes: u64,
+}
+
+/// Partitions found on a block device
+#[derive(Debug, Clone)]
+pub struct PartitionTable {
+    pub kind: TableKind,
+    pub disk_guid: Option<Guid>,
+    pub entries: Vec<PartitionEntry>,
+}
+
+impl PartitionTable {
+    /// Read and parse the partition table from LBA 0/1 of `dev`.
+    ///
+    /// A protective MBR selects GPT; the backup GPT header is used when the
+    /// primary header or its entry array fails CRC validation.
+    pub fn read<D: BlockDevice>(dev: &D) -> Result<Self, SdError> {
+        let block_size = dev.block_size();
+        let mut mbr = vec![0u8; block_size];
+        dev.read(0, &mut mbr)?;
+
+        if le_u16(&mbr, 510) != MBR_SIGNATURE {
+            debug!("No MBR signature on LBA 0");
+            return Err(SdError::InvalidPartitionTable);
+        }
+
+        let protective =
+            (0..4).any(|i| mbr[MBR_ENTRY_OFFSET + i * 16 + 4] == MBR_TYPE_GPT_PROTECTIVE);
+        if protective {
+            return read_gpt(dev);
+        }
+
+        read_mbr(dev, &mbr)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&PartitionEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&PartitionEntry> {
+        self.entries.iter().find(|entry| entry.index == index)
+    }
+
+    /// Open the partition called `name` as a bounded block device on `dev`
+    pub fn open<D: BlockDevice>(&self, dev: D, name: &str) -> Result<Partition<D>, SdError> {
+        let entry = self.find(name).ok_or(SdError::PartitionNotFound)?;
+        entry.open(dev)
+    }
+}
+
+impl PartitionEntry {
+    /// Bounded block device covering this partition on `dev`
+    pub fn open<D: BlockDevice>(&self, dev: D) -> Result<Partition<D>, SdError> {
+        Partition::new(dev, self.start_lba, self.num_blocks)
+    }
+}
+
+/// Find a partition by name on `dev`, e.g. `find_partition(&emmc, "rootfs")`
+pub fn find_partition<D: BlockDevice>(dev: D, name: &str) -> Result<Partition<D>, SdError> {
+    let table = PartitionTable::read(&dev)?;
+    table.open(dev, name)
+}
+
+fn read_mbr<D: BlockDevice>(dev: &D, mbr: &[u8]) -> Result<PartitionTable, SdError> {
+    let mut entries = Vec::new();
+    let mut extended = None;
+
+    for i in 0..4 {
+        let raw = &mbr[MBR_ENTRY_OFFSET + i * 16..MBR_ENTRY_OFFSET + (i + 1) * 16];
+        let part_type = raw[4];
+        let start = le_u32(raw, 8) as u64;
+        let count = le_u32(raw, 12) as u64;
+
+        if part_type == MBR_TYPE_EMPTY || count == 0 {
+            continue;
+        }
+
+        if MBR_TYPES_EXTENDED.contains(&part_type) {
+            if extended.is_none() {
+                extended = Some(start);
+            }
+            continue;
+        }
+
+        entries.push(mbr_entry(i + 1, raw, 0));
+    }
+
+    if let Some(ext_start) = extended {
+        read_ebr_chain(dev, ext_start, &mut entries)?;
+    }
+
+    Ok(PartitionTable {
+        kind: TableKind::Mbr,
+        disk_guid: None,
+        entries,
+    })
+}
+
+/// Walk the linked list of extended boot records starting at `ext_start`
+fn read_ebr_chain<D: BlockDevice>(
+    dev: &D,
+    ext_start: u64,
+    entries: &mut Vec<PartitionEntry>,
+) -> Result<(), SdError> {
+    let mut ebr = vec![0u8; dev.block_size()];
+    let mut ebr_lba = ext_start;
+
+    for n in 0..MBR_MAX_LOGICAL {
+        dev.read(ebr_lba, &mut ebr)?;
+        if le_u16(&ebr, 510) != MBR_SIGNATURE {
+            warn!("Invalid EBR signature at LBA {:#x}", ebr_lba);
+            break;
+        }
+
+        // First entry: the logical partition, relative to this EBR
+        let logical = &ebr[MBR_ENTRY_OFFSET..MBR_ENTRY_OFFSET + 16];
+        if logical[4] != MBR_TYPE_EMPTY && le_u32(logical, 12) != 0 {
+            entries.push(mbr_entry(5 + n, logical, ebr_lba));
+        }
+
+        // Second entry: the next EBR, relative to the extended partition
+        let next = &ebr[MBR_ENTRY_OFFSET + 16..MBR_ENTRY_OFFSET + 32];
+        let next_start = le_u32(next, 8) as u64;
+        if next[4] == MBR_TYPE_EMPTY || next_start == 0 {
+            break;
+        }
+        ebr_lba = ext_start + next_start;
+    }
+
+    Ok(())
+}
+
+fn mbr_entry(index: usize, raw: &[u8], base: u64) -> PartitionEntry {
+    PartitionEntry {
+        index,
+        name: String::new(),
+        part_type: PartitionType::Mbr(raw[4]),
+        guid: None,
+        start_lba: base + le_u32(raw, 8) as u64,
+        num_blocks: le_u32(raw, 12) as u64,
+        attributes: if raw[0] & 0x80 != 0 { 1 << 2 } else { 0 },
+    }
+}
+
+fn read_gpt<D: BlockDevice>(dev: &D) -> Result<PartitionTable, SdError> {
+    let last_lba = dev.num_blocks().saturating_sub(1);
+
+    let backup_lba = match read_gpt_at(dev, 1) {
+        Ok(table) => return Ok(table),
+        Err((e, alternate)) => {
+            warn!("Primary GPT invalid ({:?}), trying backup header", e);
+            alternate.unwrap_or(last_lba)
+        }
+    };
+
+    read_gpt_at(dev, backup_lba).map_err(|(e, _)| e)
+}
+
+/// Parse the GPT header at `lba`. On failure also returns the alternate
+/// header location if the header itself was readable.
+fn read_gpt_at<D: BlockDevice>(
+    dev: &D,
+    lba: u64,
+) -> Result<PartitionTable, (SdError, Option<u64>)> {
+    let block_size = dev.block_size();
+    let mut header = vec![0u8; block_size];
+    dev.read(lba, &mut header).map_err(|e| (e, None))?;
+
+    let invalid = |alternate| (SdError::InvalidPartitionTable, alternate);
+
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err(invalid(None));
+    }
+
+    let header_size = le_u32(&header, 12) as usize;
+    if !(GPT_HEADER_MIN_SIZE..=block_size).contains(&header_size) {
+        return Err(invalid(None));
+    }
+
+    let header_crc = le_u32(&header, 16);
+    let mut check = header[..header_size].to_vec();
+    check[16..20].fill(0);
+    if crc32(&check) != header_crc {
+        debug!("GPT header CRC mismatch at LBA {:#x}", lba);
+        return Err(invalid(None));
+    }
+
+    let my_lba = le_u64(&header, 24);
+    let alternate_lba = le_u64(&header, 32);
+    if my_lba != lba {
+        return Err(invalid(None));
+    }
+
+    let disk_guid = guid_at(&header, 56);
+    let entries_lba = le_u64(&header, 72);
+    let num_entries = le_u32(&header, 80) as usize;
+    let entry_size = le_u32(&header, 84) as usize;
+    let entries_crc = le_u32(&header, 88);
+
+    // Entries are 128 << n bytes, never more than a block
+    if num_entries > GPT_MAX_ENTRIES
+        || entry_size < GPT_ENTRY_MIN_SIZE
+        || entry_size > block_size
+        || !entry_size.is_power_of_two()
+    {
+        return Err(invalid(Some(alternate_lba)));
+    }
+
+    let array_len = num_entries * entry_size;
+    let array_blocks = array_len.div_ceil(block_size);
+    let array_end = entries_lba.checked_add(array_blocks as u64);
+    if array_end.is_none_or(|end| end > dev.num_blocks()) {
+        debug!(
+            "GPT entry array at LBA {:#x} beyond the device",
+            entries_lba
+        );
+        return Err(invalid(Some(alternate_lba)));
+    }
+
+    let mut array = vec![0u8; array_blocks * block_size];
+    dev.read(entries_lba, &mut array)
+        .map_err(|e| (e, Some(alternate_lba)))?;
+
+    if crc32(&array[..array_len]) != entries_crc {
+        debug!("GPT entry array CRC mismatch at LBA {:#x}", entries_lba);
+        return Err(invalid(Some(alternate_lba)));
+    }
+
+    let mut entries = Vec::new();
+    for (i, raw) in array[..array_len].chunks_exact(entry_size).enumerate() {
+        let type_guid = guid_at(raw, 0);
+        if type_guid.is_zero() {
+            continue;
+        }
+
+        let first = le_u64(raw, 32);
+        let last = le_u64(raw, 40);
+        if last < first {
+            warn!("GPT entry {} has an invalid range, skipped", i + 1);
+            continue;
+        }
+
+        let name_units = raw[56..128]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0);
+        let name = char::decode_utf16(name_units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        entries.push(PartitionEntry {
+            index: i + 1,
+            name,
+            part_type: PartitionType::Gpt(type_guid),
+            guid: Some(guid_at(raw, 16)),
+            start_lba: first,
+            num_blocks: last - first + 1,
+            attributes: le_u64(raw, 48),
+        });
+    }
+
+    Ok(PartitionTable {
+        kind: TableKind::Gpt,
+        disk_guid: Some(disk_guid),
+        entries,
+    })
+}
+
+/// CRC32 (IEEE 802.3, reflected), as used by GPT
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn guid_at(buf: &[u8], offset: usize) -> Guid {
+    let mut guid = [0u8; 16];
+    guid.copy_from_slice(&buf[offset..offset + 16]);
+    Guid(guid)
+}
+
+fn le_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn le_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
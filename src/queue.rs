@@ -0,0 +1,276 @@
+// ===== I/O Request Queue =====
+
+use alloc::{collections::BTreeMap, collections::BTreeSet, vec, vec::Vec};
+
+use log::trace;
+
+use crate::{
+    block::{BlockDevice, buf_blocks},
+    err::SdError,
+};
+
+/// Pending blocks that trigger an automatic dispatch, used by `IoQueue::new`
+pub const DEFAULT_BATCH_BLOCKS: usize = 256;
+/// Largest merged request, used by `IoQueue::new`
+pub const DEFAULT_MERGE_BLOCKS: usize = 128;
+
+pub type RequestId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoKind {
+    Read,
+    Write,
+}
+
+/// Result of one submitted request
+#[derive(Debug)]
+pub struct Completion {
+    pub id: RequestId,
+    pub kind: IoKind,
+    pub block_id: u64,
+    /// Data read, empty for writes
+    pub data: Vec<u8>,
+    pub result: Result<(), SdError>,
+}
+
+struct Request {
+    id: RequestId,
+    kind: IoKind,
+    block_id: u64,
+    blocks: usize,
+    /// Write payload
+    data: Vec<u8>,
+}
+
+/// Queue that collects single block requests and dispatches them as few,
+/// large, sorted transfers.
+///
+/// Contiguous reads and writes are merged so that the card sees one
+/// multi-block command (CMD18/CMD25) instead of one command per block.
+/// Requests behave as if executed in submission order: a read returns the
+/// data of writes submitted before it, even if they are still queued.
+pub struct IoQueue<D: BlockDevice> {
+    dev: D,
+    pending: Vec<Request>,
+    pending_blocks: usize,
+    completed: Vec<Completion>,
+    next_id: RequestId,
+    batch_blocks: usize,
+    merge_blocks: usize,
+}
+
+impl<D: BlockDevice> IoQueue<D> {
+    pub fn new(dev: D) -> Self {
+        Self {
+            dev,
+            pending: Vec::new(),
+            pending_blocks: 0,
+            completed: Vec::new(),
+            next_id: 0,
+            batch_blocks: DEFAULT_BATCH_BLOCKS,
+            merge_blocks: DEFAULT_MERGE_BLOCKS,
+        }
+    }
+
+    /// Dispatch automatically once this many blocks are pending
+    pub fn with_batch_blocks(mut self, blocks: usize) -> Self {
+        self.batch_blocks = blocks.max(1);
+        self
+    }
+
+    /// Upper bound on the size of a merged transfer
+    pub fn with_merge_blocks(mut self, blocks: usize) -> Self {
+        self.merge_blocks = blocks.max(1);
+        self
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.dev
+    }
+
+    /// Number of requests not yet dispatched
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn submit_read(&mut self, block_id: u64, blocks: usize) -> Result<RequestId, SdError> {
+        self.check_range(block_id, blocks)?;
+        Ok(self.push(IoKind::Read, block_id, blocks, Vec::new()))
+    }
+
+    pub fn submit_write(&mut self, block_id: u64, data: Vec<u8>) -> Result<RequestId, SdError> {
+        if self.dev.is_read_only() {
+            return Err(SdError::WriteProtected);
+        }
+
+        let blocks = buf_blocks(data.len(), self.dev.block_size())? as usize;
+        self.check_range(block_id, blocks)?;
+        Ok(self.push(IoKind::Write, block_id, blocks, data))
+    }
+
+    /// Execute all pending requests and return every completion since the last call
+    pub fn dispatch(&mut self) -> Vec<Completion> {
+        self.run_batch();
+        core::mem::take(&mut self.completed)
+    }
+
+    fn check_range(&self, block_id: u64, blocks: usize) -> Result<(), SdError> {
+        let end = block_id
+            .checked_add(blocks as u64)
+            .ok_or(SdError::OutOfRange)?;
+        if blocks == 0 || end > self.dev.num_blocks() {
+            return Err(SdError::OutOfRange);
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, kind: IoKind, block_id: u64, blocks: usize, data: Vec<u8>) -> RequestId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.pending.push(Request {
+            id,
+            kind,
+            block_id,
+            blocks,
+            data,
+        });
+        self.pending_blocks += blocks;
+
+        if self.pending_blocks >= self.batch_blocks {
+            self.run_batch();
+        }
+
+        id
+    }
+
+    /// Split sorted block numbers into contiguous runs of at most `merge_blocks`
+    fn runs(&self, blocks: impl Iterator<Item = u64>) -> Vec<(u64, usize)> {
+        let mut runs: Vec<(u64, usize)> = Vec::new();
+        for lba in blocks {
+            match runs.last_mut() {
+                Some((start, len)) if *start + *len as u64 == lba && *len < self.merge_blocks => {
+                    *len += 1
+                }
+                _ => runs.push((lba, 1)),
+            }
+        }
+        runs
+    }
+
+    fn run_batch(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let requests = core::mem::take(&mut self.pending);
+        self.pending_blocks = 0;
+        let bs = self.dev.block_size();
+
+        // Latest write per block, in submission order: block -> (request index, offset)
+        let mut writes: BTreeMap<u64, (usize, usize)> = BTreeMap::new();
+        // Every block a read needs from the device
+        let mut read_blocks: BTreeSet<u64> = BTreeSet::new();
+
+        for (index, req) in requests.iter().enumerate() {
+            for i in 0..req.blocks {
+                let lba = req.block_id + i as u64;
+                match req.kind {
+                    IoKind::Write => {
+                        writes.insert(lba, (index, i * bs));
+                    }
+                    IoKind::Read => {
+                        read_blocks.insert(lba);
+                    }
+                }
+            }
+        }
+
+        // Reads go first so they observe the card before this batch's writes
+        let mut read_data: BTreeMap<u64, Result<Vec<u8>, SdError>> = BTreeMap::new();
+        for (start, len) in self.runs(read_blocks.into_iter()) {
+            trace!("Queue read {:#x} +{}", start, len);
+            let mut buf = vec![0u8; len * bs];
+            let result = self.dev.read(start, &mut buf);
+            for i in 0..len {
+                let block = match &result {
+                    Ok(()) => Ok(buf[i * bs..(i + 1) * bs].to_vec()),
+                    Err(e) => Err(e.clone()),
+                };
+                read_data.insert(start + i as u64, block);
+            }
+        }
+
+        // Then the surviving version of every written block
+        let mut write_errors: BTreeMap<usize, SdError> = BTreeMap::new();
+        for (start, len) in self.runs(writes.keys().copied()) {
+            trace!("Queue write {:#x} +{}", start, len);
+            let mut buf = Vec::with_capacity(len * bs);
+            for i in 0..len {
+                let (index, offset) = writes[&(start + i as u64)];
+                buf.extend_from_slice(&requests[index].data[offset..offset + bs]);
+            }
+
+            if let Err(e) = self.dev.write(start, &buf) {
+                for i in 0..len {
+                    let (index, _) = writes[&(start + i as u64)];
+                    write_errors.insert(index, e.clone());
+                }
+            }
+        }
+
+        for (index, req) in requests.iter().enumerate() {
+            let completion = match req.kind {
+                IoKind::Write => Completion {
+                    id: req.id,
+                    kind: req.kind,
+                    block_id: req.block_id,
+                    data: Vec::new(),
+                    result: write_errors.remove(&index).map_or(Ok(()), Err),
+                },
+                IoKind::Read => self.complete_read(&requests, index, &read_data),
+            };
+            self.completed.push(completion);
+        }
+    }
+
+    /// Assemble a read from device data, forwarding writes submitted before it
+    fn complete_read(
+        &self,
+        requests: &[Request],
+        index: usize,
+        read_data: &BTreeMap<u64, Result<Vec<u8>, SdError>>,
+    ) -> Completion {
+        let req = &requests[index];
+        let bs = self.dev.block_size();
+        let mut data = vec![0u8; req.blocks * bs];
+        let mut result = Ok(());
+
+        for i in 0..req.blocks {
+            let lba = req.block_id + i as u64;
+            let dst = &mut data[i * bs..(i + 1) * bs];
+
+            let forwarded = requests[..index].iter().rev().find(|w| {
+                w.kind == IoKind::Write && (w.block_id..w.block_id + w.blocks as u64).contains(&lba)
+            });
+
+            match (forwarded, &read_data[&lba]) {
+                (Some(w), _) => {
+                    let offset = (lba - w.block_id) as usize * bs;
+                    dst.copy_from_slice(&w.data[offset..offset + bs]);
+                }
+                (None, Ok(block)) => dst.copy_from_slice(block),
+                (None, Err(e)) => result = Err(e.clone()),
+            }
+        }
+
+        Completion {
+            id: req.id,
+            kind: IoKind::Read,
+            block_id: req.block_id,
+            data,
+            result,
+        }
+    }
+}
@@ -13,32 +13,26 @@ mod tests {
         println,
         time::since_boot,
     };
-    use dma_api::{DVec, Direction};
     use log::{info, warn};
     use rk3568_clk::RK3568ClkPriv;
     use sdmmc::emmc::EMmcHost;
     use sdmmc::emmc::constant::*;
     use sdmmc::{
         Kernel,
-        emmc::clock::{Clk, ClkError, init_global_clk},
-        set_impl,
+        block::{BlockDevice, Partition},
+        cache::BlockCache,
+        emmc::clock::{Clk, ClkError},
+        partition::PartitionTable,
     };
 
     struct SKernel;
 
     impl Kernel for SKernel {
-        fn sleep(us: u64) {
-            let start = since_boot();
-            let duration = core::time::Duration::from_micros(us);
-
-            while since_boot() - start < duration {
-                core::hint::spin_loop();
-            }
+        fn now(&self) -> core::time::Duration {
+            since_boot()
         }
     }
 
-    set_impl!(SKernel);
-
     #[test]
     fn test_platform() {
         let PlatformInfoKind::DeviceTree(fdt) = &global_val().platform_info;
@@ -72,15 +66,9 @@ mod tests {
 
         info!("EMMC: {} Clock: {}", emmc.name, clock.name);
 
-        let emmc_reg = emmc.reg().unwrap().next().unwrap();
         let clk_reg = clock.reg().unwrap().next().unwrap();
         // let syscon_reg = syscon.reg().unwrap().next().unwrap();
 
-        println!(
-            "EMMC reg {:#x}, {:#x}",
-            emmc_reg.address,
-            emmc_reg.size.unwrap()
-        );
         println!(
             "Clock reg {:#x}, {:#x}",
             clk_reg.address,
@@ -88,14 +76,19 @@ mod tests {
         );
         // println!("Syscon reg {:#x}, {:#x}", syscon_reg.address, syscon_reg.size.unwrap());
 
-        let emmc_addr_ptr = iomap((emmc_reg.address as usize).into(), emmc_reg.size.unwrap());
         let clk_add_ptr = iomap((clk_reg.address as usize).into(), clk_reg.size.unwrap());
         // let syscon_addr_ptr = iomap((syscon_reg.address as usize).into(), syscon_reg.size.unwrap());
 
-        let emmc_addr = emmc_addr_ptr.as_ptr() as usize;
         let clk_addr = clk_add_ptr.as_ptr() as usize;
 
-        test_emmc(emmc_addr, clk_addr);
+        let emmc_host = EMmcHost::from_fdt(&emmc, |address, size| {
+            println!("EMMC reg {:#x}, {:#x}", address, size);
+            iomap((address as usize).into(), size).as_ptr() as usize
+        })
+        .unwrap();
+        println!("EMMC config: {:?}", emmc_host.config());
+
+        test_emmc(emmc_host, clk_addr);
 
         info!("test uboot");
     }
@@ -126,29 +119,42 @@ mod tests {
         }
     }
 
-    fn init_clk(clk_addr: usize) -> Result<(), ClkError> {
-        let cru = ClkUnit::new(unsafe { RK3568ClkPriv::new(clk_addr as *mut _) });
+    // The CRU is only touched through the host that owns this provider
+    unsafe impl Send for ClkUnit {}
 
-        let static_clk: &'static dyn Clk = Box::leak(Box::new(cru));
-        init_global_clk(static_clk);
-        Ok(())
-    }
-
-    fn test_emmc(emmc_addr: usize, clock: usize) {
-        // Initialize custom SDHCI controller
-        let mut emmc = EMmcHost::new(emmc_addr);
-        let _ = init_clk(clock);
+    fn test_emmc(emmc: EMmcHost, clock: usize) {
+        let cru = ClkUnit::new(unsafe { RK3568ClkPriv::new(clock as *mut _) });
+        let mut emmc = emmc.with_clk(Box::new(cru)).with_kernel(&SKernel);
 
         // Try to initialize the SD card
         match emmc.init() {
             Ok(_) => {
                 println!("SD card initialization successful!");
+                println!("Card clock: {} Hz", emmc.actual_clock());
 
                 // Get card information
                 match emmc.get_card_info() {
                     Ok(card_info) => {
                         println!("Card type: {:?}", card_info.card_type);
-                        println!("Manufacturer ID: 0x{:02X}", card_info.manufacturer_id);
+                        println!(
+                            "Manufacturer: {} (0x{:02X}), OEM: 0x{:04X}",
+                            card_info.manufacturer_name,
+                            card_info.manufacturer_id,
+                            card_info.oem_id
+                        );
+                        println!(
+                            "Product: {} rev {}.{}, serial: 0x{:08X}, date: {}/{}",
+                            card_info.product_name,
+                            card_info.product_revision.0,
+                            card_info.product_revision.1,
+                            card_info.serial_number,
+                            card_info.manufacturing_month,
+                            card_info.manufacturing_year
+                        );
+                        println!(
+                            "CSD structure: {}, spec version: {}",
+                            card_info.csd_structure, card_info.spec_version
+                        );
                         println!("Capacity: {} MB", card_info.capacity_bytes / (1024 * 1024));
                         println!("Block size: {} bytes", card_info.block_size);
                     }
@@ -157,17 +163,39 @@ mod tests {
                     }
                 }
 
-                // Test reading the first block
-                println!("Attempting to read first block...");
+                match emmc.health() {
+                    Ok(health) => {
+                        println!(
+                            "Health: {:?} (pre-EOL {:?}, life time A {:?}, B {:?})",
+                            health.status(),
+                            health.pre_eol_info,
+                            health.life_time_est_a.used_percent(),
+                            health.life_time_est_b.used_percent()
+                        );
+                    }
+                    Err(e) => {
+                        warn!("Failed to get device health: {:?}", e);
+                    }
+                }
 
-                cfg_if::cfg_if! {
-                    if #[cfg(feature = "dma")] {
-                        let mut buffer: DVec<u8> = DVec::zeros(MMC_MAX_BLOCK_LEN as usize, 0x1000, Direction::FromDevice).unwrap();
-                    } else if #[cfg(feature = "pio")] {
-                        let mut buffer: [u8; 512] = [0; 512];
+                match emmc.get_status() {
+                    Ok(status) => {
+                        println!(
+                            "Card status: {:?}, tracked state: {:?}",
+                            status,
+                            emmc.card_state()
+                        );
+                    }
+                    Err(e) => {
+                        warn!("Failed to get card status: {:?}", e);
                     }
                 }
 
+                // Test reading the first block
+                println!("Attempting to read first block...");
+
+                let mut buffer: [u8; 512] = [0; 512];
+
                 match emmc.read_blocks(5034498, 1, &mut buffer) {
                     Ok(_) => {
                         println!("Successfully read first block!");
@@ -183,21 +211,10 @@ mod tests {
                 println!("Testing write and read back...");
                 let test_block_id = 0x3; // Use a safe block address for testing
 
-                cfg_if::cfg_if! {
-                    if #[cfg(feature = "dma")] {
-                        // Prepare test pattern data
-                        let mut write_buffer = DVec::zeros(512, 0x1000, Direction::ToDevice).unwrap();
-                        for i in 0..512 {
-                            // write_buffer.set(i, (i % 256) as u8);
-                            write_buffer.set(i, 0 as u8); // Fill with test pattern data
-                        }
-                    } else if #[cfg(feature = "pio")] {
-                        let mut write_buffer: [u8; 512] = [0; 512];
-                        for i in 0..512 {
-                            // write_buffer[i] = (i % 256) as u8; // Fill with test pattern data
-                            write_buffer[i] = 0 as u8;
-                        }
-                    }
+                let mut write_buffer: [u8; 512] = [0; 512];
+                for i in 0..512 {
+                    // write_buffer[i] = (i % 256) as u8; // Fill with test pattern data
+                    write_buffer[i] = 0 as u8;
                 }
 
                 // Write data
@@ -206,13 +223,7 @@ mod tests {
                         println!("Successfully wrote to block {}!", test_block_id);
 
                         // Read back data
-                        cfg_if::cfg_if! {
-                            if #[cfg(feature = "dma")] {
-                                let mut read_buffer: DVec<u8> = DVec::zeros(MMC_MAX_BLOCK_LEN as usize, 0x1000, Direction::FromDevice).unwrap();
-                            } else if #[cfg(feature = "pio")] {
-                                let mut read_buffer: [u8; 512] = [0; 512];
-                            }
-                        }
+                        let mut read_buffer: [u8; 512] = [0; 512];
 
                         match emmc.read_blocks(test_block_id, 1, &mut read_buffer) {
                             Ok(_) => {
@@ -260,14 +271,8 @@ mod tests {
                 println!("Testing multi-block read...");
                 let multi_block_addr = 200;
                 let block_count = 4; // Read 4 blocks
-                cfg_if::cfg_if! {
-                    if #[cfg(feature = "dma")] {
-                        let mut multi_buffer: DVec<u8> = DVec::zeros(MMC_MAX_BLOCK_LEN as usize * block_count as usize, 0x1000, Direction::FromDevice).unwrap();
-                    } else if #[cfg(feature = "pio")] {
-                        // Using a fixed size of 2048 (which is 512 * 4) instead of computing it at runtime
-                        let mut multi_buffer: [u8; 2048] = [0; 2048];
-                    }
-                }
+                // Using a fixed size of 2048 (which is 512 * 4) instead of computing it at runtime
+                let mut multi_buffer: [u8; 2048] = [0; 2048];
 
                 match emmc.read_blocks(multi_block_addr, block_count, &mut multi_buffer) {
                     Ok(_) => {
@@ -289,6 +294,49 @@ mod tests {
                         warn!("Multi-block read failed: {:?}", e);
                     }
                 }
+
+                // Test the transfer mode independent block device interface
+                println!("Testing BlockDevice interface...");
+                let mut block = [0u8; 512];
+                match BlockDevice::read(&emmc, 0, &mut block) {
+                    Ok(_) => println!(
+                        "Device has {} blocks, block 0 ends with {:02X?}",
+                        emmc.num_blocks(),
+                        &block[510..]
+                    ),
+                    Err(e) => warn!("BlockDevice read failed: {:?}", e),
+                }
+
+                match Partition::new(&emmc, multi_block_addr, block_count as u64) {
+                    Ok(part) => match part.read(0, &mut block) {
+                        Ok(_) => println!("Partition view block 0: {:02X?}", &block[..16]),
+                        Err(e) => warn!("Partition view read failed: {:?}", e),
+                    },
+                    Err(e) => warn!("Failed to create partition view: {:?}", e),
+                }
+
+                // Test partition table parsing
+                match PartitionTable::read(&emmc) {
+                    Ok(table) => {
+                        println!("Partition table: {:?}", table.kind);
+                        for entry in &table.entries {
+                            println!(
+                                "  #{} {:>10} start {:#x} blocks {:#x}",
+                                entry.index, entry.name, entry.start_lba, entry.num_blocks
+                            );
+                        }
+                    }
+                    Err(e) => warn!("Failed to read partition table: {:?}", e),
+                }
+
+                // Test the block cache, the second read must be a hit
+                let cache = BlockCache::with_capacity(&emmc, 16).with_read_ahead(4);
+                for _ in 0..2 {
+                    if let Err(e) = cache.read(0, &mut block) {
+                        warn!("Cached read failed: {:?}", e);
+                    }
+                }
+                println!("Block cache stats: {:?}", cache.stats());
             }
             Err(e) => {
                 warn!("SD card initialization failed: {:?}", e);